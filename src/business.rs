@@ -1,58 +1,626 @@
 use anyhow::Result;
-use std::collections::HashSet;
-use tokio::sync::Mutex;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, broadcast};
+
+use crate::config::Config;
+use crate::protocol::{Event, MatchStrategy, WindowEntry, WindowState};
+use crate::scope::{FollowScope, WorkspacePattern};
+use crate::selector::Selector;
+use crate::state::{DaemonState, FloatingGeometry, SavedWindow, WindowOrigin};
+use crate::system_integration::{WindowInfo, WorkspaceInfo};
+
+/// Maximum number of windows remembered in the focus history
+const FOCUS_HISTORY_LIMIT: usize = 64;
+
+/// Events buffered per `watch` subscriber before it misses some
+const EVENT_BUFFER: usize = 256;
+/// How long a focus change the daemon makes is waited for in the event stream
+const OWN_FOCUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A window focus recorded from niri's event stream
+#[derive(Debug, Clone, Copy)]
+pub struct FocusEntry {
+    pub id: u64,
+    pub at: Instant,
+}
+
+/// Set of window ids that remembers when and in which order each window joined it
+#[derive(Debug, Default, Clone)]
+pub struct WindowSet {
+    /// Members with the time they joined, oldest first
+    members: Vec<(u64, Instant)>,
+}
+
+impl WindowSet {
+    /// Add a window, returning `true` if it was not already present
+    /// Re-adding a present window keeps its original timestamp and position
+    pub fn insert(&mut self, id: u64) -> bool {
+        if self.contains(&id) {
+            return false;
+        }
+        self.members.push((id, Instant::now()));
+        true
+    }
+
+    /// Remove a window, returning `true` if it was present
+    pub fn remove(&mut self, id: &u64) -> bool {
+        let len = self.members.len();
+        self.members.retain(|(member, _)| member != id);
+        self.members.len() != len
+    }
+
+    pub fn contains(&self, id: &u64) -> bool {
+        self.members.iter().any(|(member, _)| member == id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Members, oldest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &u64> {
+        self.members.iter().map(|(id, _)| id)
+    }
+
+    /// When the window joined the set
+    pub fn since(&self, id: &u64) -> Option<Instant> {
+        self.members
+            .iter()
+            .find(|(member, _)| member == id)
+            .map(|(_, at)| *at)
+    }
+
+    /// Members with the Unix time they joined, oldest first
+    pub fn save(&self) -> Vec<SavedWindow> {
+        let now = SystemTime::now();
+        self.members
+            .iter()
+            .map(|(id, at)| SavedWindow {
+                id: *id,
+                since: (now - at.elapsed())
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            })
+            .collect()
+    }
+
+    /// Rebuild a set from saved windows, keeping only windows that still exist
+    /// The saved order wins over the timestamps, which only have second resolution
+    pub fn restore(saved: &[SavedWindow], existing: &HashSet<u64>) -> Self {
+        let now = Instant::now();
+        let now_unix = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let members = saved
+            .iter()
+            .filter(|w| existing.contains(&w.id))
+            .map(|w| {
+                let age = Duration::from_secs(now_unix.saturating_sub(w.since));
+                (w.id, now.checked_sub(age).unwrap_or(now))
+            })
+            .collect();
+        Self { members }
+    }
+}
+
+/// Where `stage cycle` is, kept between presses
+#[derive(Debug, Default)]
+struct StageCycle {
+    /// Staged windows when the cycle started, most recently staged first
+    order: Vec<u64>,
+    position: usize,
+    /// Window the cycle brought out last
+    shown: Option<u64>,
+    /// Where the shown window was originally staged from
+    shown_origin: Option<WindowOrigin>,
+}
+
+/// What one press of `stage cycle` does
+#[derive(Debug, PartialEq)]
+struct CycleStep {
+    /// Staged window to bring out
+    show: u64,
+    /// Window the cycle showed before, to send back to the stage first
+    send_back: Option<u64>,
+}
+
+impl StageCycle {
+    /// Move to the next window, given the staged windows most recently staged first
+    /// The cycle starts over whenever the stage changed outside of it, leaving the
+    /// window it showed last out; `None` if the stage is empty
+    fn advance(
+        &mut self,
+        staged: Vec<u64>,
+        exists: impl Fn(u64) -> bool,
+        reverse: bool,
+    ) -> Option<CycleStep> {
+        let shown = self.shown.filter(|id| exists(*id) && !staged.contains(id));
+        let continuing = shown.is_some() && {
+            let mut expected: Vec<u64> = self
+                .order
+                .iter()
+                .copied()
+                .filter(|id| Some(*id) != shown)
+                .collect();
+            let mut current = staged.clone();
+            expected.sort_unstable();
+            current.sort_unstable();
+            expected == current
+        };
+
+        if continuing {
+            let len = self.order.len();
+            self.position = if reverse {
+                (self.position + len - 1) % len
+            } else {
+                (self.position + 1) % len
+            };
+        } else if staged.is_empty() {
+            *self = StageCycle::default();
+            return None;
+        } else {
+            *self = StageCycle {
+                position: if reverse { staged.len() - 1 } else { 0 },
+                order: staged,
+                ..Default::default()
+            };
+        }
+
+        let show = self.order[self.position];
+        Some(CycleStep {
+            show,
+            send_back: self.shown.filter(|id| *id != show),
+        })
+    }
+}
+
+/// A staged window shown temporarily by `stage peek`
+#[derive(Debug, Clone, Copy)]
+struct Peek {
+    id: u64,
+    /// Workspace the window is shown on; switching away ends the peek
+    workspace_id: u64,
+    /// Set once niri reports the window focused, after which losing focus ends the peek
+    focused: bool,
+    /// Tells this peek apart from later ones when its timeout fires
+    started: Instant,
+}
+
+/// Where an unstaged window goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    /// This workspace, usually the active one
+    Workspace(u64),
+    /// The workspace and position the window was staged from, else the fallback workspace
+    Origin { fallback: u64 },
+}
+
+/// Describe a niri window together with its nsticky state
+fn describe_window(
+    window: &WindowInfo,
+    workspaces: &[WorkspaceInfo],
+    sticky: &WindowSet,
+    staged: &WindowSet,
+) -> WindowEntry {
+    let workspace = workspaces
+        .iter()
+        .find(|ws| Some(ws.id) == window.workspace_id);
+    let (state, since) = if staged.contains(&window.id) {
+        (WindowState::Staged, staged.since(&window.id))
+    } else if sticky.contains(&window.id) {
+        (WindowState::Sticky, sticky.since(&window.id))
+    } else {
+        (WindowState::Normal, None)
+    };
+
+    WindowEntry {
+        id: window.id,
+        app_id: window.app_id.clone(),
+        title: window.title.clone(),
+        workspace_id: window.workspace_id,
+        workspace_idx: workspace.map(|ws| ws.idx),
+        workspace_name: workspace.and_then(|ws| ws.name.clone()),
+        output: workspace.and_then(|ws| ws.output.clone()),
+        is_floating: window.is_floating,
+        state,
+        since_secs: since.map(|at| at.elapsed().as_secs()),
+    }
+}
+
+/// Narrow matching windows down according to the strategy
+/// `Mru` orders by the daemon's focus history (most recent first), then niri's focus timestamp
+fn select_windows(
+    mut matches: Vec<WindowInfo>,
+    strategy: MatchStrategy,
+    history: &VecDeque<FocusEntry>,
+) -> Vec<u64> {
+    match strategy {
+        MatchStrategy::All => matches.into_iter().map(|w| w.id).collect(),
+        MatchStrategy::First => matches.first().map(|w| w.id).into_iter().collect(),
+        MatchStrategy::Focused => matches
+            .into_iter()
+            .filter(|w| w.is_focused)
+            .map(|w| w.id)
+            .collect(),
+        MatchStrategy::Mru => {
+            let rank = |id: u64| {
+                history
+                    .iter()
+                    .position(|e| e.id == id)
+                    .unwrap_or(usize::MAX)
+            };
+            matches.sort_by_key(|w| (rank(w.id), std::cmp::Reverse(w.focus_timestamp)));
+            matches.first().map(|w| w.id).into_iter().collect()
+        }
+    }
+}
+
+/// niri actions that put a window back into its origin layout
+#[derive(Debug, Default, PartialEq)]
+struct LayoutPlan {
+    /// Float (`true`) or tile (`false`) the window first
+    set_floating: Option<bool>,
+    /// Floating position and size to restore
+    place: Option<FloatingGeometry>,
+    /// Column to move the window's column back to
+    column: Option<u64>,
+}
+
+/// Decide how to restore a window's layout from its origin and where it is now
+/// niri only moves the focused column, so the column is restored only when the
+/// workspace is on screen
+fn plan_layout(origin: &WindowOrigin, is_floating: bool, on_screen: bool) -> LayoutPlan {
+    if origin.was_floating {
+        return LayoutPlan {
+            set_floating: (!is_floating).then_some(true),
+            place: origin.floating,
+            column: None,
+        };
+    }
+    LayoutPlan {
+        set_floating: is_floating.then_some(false),
+        place: None,
+        column: origin.column.filter(|_| on_screen),
+    }
+}
+
+/// Bring a staged window onto a workspace and send the focused one to the stage
+/// If the outgoing window can't be staged, the incoming one goes back and focus returns
+async fn exchange_windows(
+    incoming: u64,
+    outgoing: u64,
+    workspace_id: u64,
+    stage_workspace: &str,
+) -> Result<()> {
+    crate::system_integration::move_to_workspace(incoming, workspace_id).await?;
+    if let Err(e) = crate::system_integration::focus_window(incoming).await {
+        tracing::warn!("Failed to focus window {incoming}: {e:#}");
+    }
+    if let Err(e) =
+        crate::system_integration::move_to_named_workspace(outgoing, stage_workspace).await
+    {
+        if let Err(undo) =
+            crate::system_integration::move_to_named_workspace(incoming, stage_workspace).await
+        {
+            tracing::error!("Failed to return window {incoming} to the stage: {undo:#}");
+        }
+        if let Err(undo) = crate::system_integration::focus_window(outgoing).await {
+            tracing::warn!("Failed to focus window {outgoing} again: {undo:#}");
+        }
+        return Err(e);
+    }
+    Ok(())
+}
 
 #[derive(Clone)]
 pub struct BusinessLogic {
-    sticky_windows: std::sync::Arc<Mutex<HashSet<u64>>>,
-    staged_set: std::sync::Arc<Mutex<HashSet<u64>>>,
+    sticky_windows: std::sync::Arc<Mutex<WindowSet>>,
+    staged_set: std::sync::Arc<Mutex<WindowSet>>,
+    focus_history: std::sync::Arc<Mutex<VecDeque<FocusEntry>>>,
+    /// Name of the workspace staged windows are moved to
+    stage_workspace: String,
+    /// State changes for `watch` subscribers
+    events: broadcast::Sender<Event>,
+    /// Follow scopes set from the CLI, by window id
+    follow_scopes: std::sync::Arc<Mutex<HashMap<u64, FollowScope>>>,
+    /// Follow scopes from the config for windows without their own
+    follow_rules: std::sync::Arc<Vec<(Selector, FollowScope)>>,
+    /// Workspaces sticky windows never follow onto, besides the stage workspace
+    reserved_workspaces: std::sync::Arc<Vec<WorkspacePattern>>,
+    /// Where staged windows were staged from, by window id
+    origins: std::sync::Arc<Mutex<HashMap<u64, WindowOrigin>>>,
+    /// Return unstaged windows to their origin unless told otherwise
+    unstage_to_origin: bool,
+    /// Staged windows that were normal, not sticky, before staging
+    staged_normal: std::sync::Arc<Mutex<HashSet<u64>>>,
+    stage_cycle: std::sync::Arc<Mutex<StageCycle>>,
+    /// Staged window shown by `stage peek`, if any
+    peek: std::sync::Arc<Mutex<Option<Peek>>>,
+    /// How long a peeked window stays out by default
+    peek_timeout: Option<Duration>,
+    /// Focus changes the daemon made itself, not yet seen in the event stream
+    own_focus: std::sync::Arc<Mutex<VecDeque<FocusEntry>>>,
 }
 
 impl BusinessLogic {
     pub fn new(
-        sticky_windows: std::sync::Arc<Mutex<HashSet<u64>>>,
-        staged_set: std::sync::Arc<Mutex<HashSet<u64>>>,
+        sticky_windows: std::sync::Arc<Mutex<WindowSet>>,
+        staged_set: std::sync::Arc<Mutex<WindowSet>>,
+        focus_history: std::sync::Arc<Mutex<VecDeque<FocusEntry>>>,
+        follow_rules: Vec<(Selector, FollowScope)>,
+        config: &Config,
     ) -> Self {
         Self {
             sticky_windows,
             staged_set,
+            focus_history,
+            stage_workspace: config.stage_workspace.clone(),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            follow_scopes: Default::default(),
+            follow_rules: std::sync::Arc::new(follow_rules),
+            reserved_workspaces: std::sync::Arc::new(config.reserved_workspaces.clone()),
+            origins: Default::default(),
+            unstage_to_origin: config.unstage_to_origin,
+            staged_normal: Default::default(),
+            stage_cycle: Default::default(),
+            peek: Default::default(),
+            peek_timeout: (config.peek_timeout > 0)
+                .then(|| Duration::from_secs(config.peek_timeout)),
+            own_focus: Default::default(),
+        }
+    }
+
+    /// Destination for unstaging onto the active workspace, or to the origin
+    /// `origin` overrides the configured default
+    pub fn destination(&self, workspace_id: u64, origin: Option<bool>) -> Destination {
+        if origin.unwrap_or(self.unstage_to_origin) {
+            Destination::Origin {
+                fallback: workspace_id,
+            }
+        } else {
+            Destination::Workspace(workspace_id)
+        }
+    }
+
+    /// Receive every state change from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Tell `watch` subscribers about a state change, if there are any
+    pub fn emit(&self, event: Event) {
+        let _ = self.events.send(event);
+    }
+
+    /// Current sticky and staged windows, the first event of a subscription
+    pub async fn snapshot(&self) -> Result<Event> {
+        Ok(Event::Snapshot {
+            sticky: self.list_sticky_windows().await?,
+            staged: self.list_staged_windows().await?,
+        })
+    }
+
+    /// Snapshot the sticky and staged windows for saving
+    pub async fn save_state(&self) -> DaemonState {
+        DaemonState {
+            niri_socket: crate::state::niri_session(),
+            sticky: self.sticky_windows.lock().await.save(),
+            staged: self.staged_set.lock().await.save(),
+            follow_scopes: self.follow_scopes.lock().await.clone(),
+            origins: self.origins.lock().await.clone(),
+            staged_normal: self.staged_normal.lock().await.clone(),
+        }
+    }
+
+    /// Restore saved sticky and staged windows, dropping those that no longer exist
+    /// Returns the number of sticky and staged windows restored
+    pub async fn restore_state(&self, state: &DaemonState) -> Result<(usize, usize)> {
+        let existing = crate::system_integration::get_full_window_list().await?;
+        let sticky = WindowSet::restore(&state.sticky, &existing);
+        let staged = WindowSet::restore(&state.staged, &existing);
+        let counts = (sticky.len(), staged.len());
+        *self.sticky_windows.lock().await = sticky;
+        *self.follow_scopes.lock().await = state
+            .follow_scopes
+            .iter()
+            .filter(|(id, _)| existing.contains(id))
+            .map(|(id, scope)| (*id, scope.clone()))
+            .collect();
+        *self.origins.lock().await = state
+            .origins
+            .iter()
+            .filter(|(id, _)| existing.contains(id))
+            .map(|(id, origin)| (*id, origin.clone()))
+            .collect();
+        *self.staged_normal.lock().await = state
+            .staged_normal
+            .iter()
+            .copied()
+            .filter(|id| staged.contains(id))
+            .collect();
+        *self.staged_set.lock().await = staged;
+        Ok(counts)
+    }
+
+    /// Record that a window gained focus, moving it to the front of the history
+    pub async fn record_focus(&self, window_id: u64) {
+        let mut history = self.focus_history.lock().await;
+        history.retain(|e| e.id != window_id);
+        history.push_front(FocusEntry {
+            id: window_id,
+            at: Instant::now(),
+        });
+        history.truncate(FOCUS_HISTORY_LIMIT);
+    }
+
+    /// Whether a focus change was made by the daemon rather than the user
+    /// Each focus the daemon makes is matched once, then forgotten
+    pub async fn is_own_focus(&self, window_id: Option<u64>) -> bool {
+        let Some(window_id) = window_id else {
+            return false;
+        };
+        let mut own = self.own_focus.lock().await;
+        own.retain(|e| e.at.elapsed() < OWN_FOCUS_TIMEOUT);
+        let Some(index) = own.iter().position(|e| e.id == window_id) else {
+            return false;
+        };
+        own.remove(index);
+        true
+    }
+
+    /// Focus a window without it counting as the user's focus
+    async fn focus_quietly(&self, window_id: u64) -> Result<()> {
+        self.own_focus.lock().await.push_back(FocusEntry {
+            id: window_id,
+            at: Instant::now(),
+        });
+        crate::system_integration::focus_window(window_id).await
+    }
+
+    /// Drop a closed window from the focus history and the sticky and staged sets
+    pub async fn forget_window(&self, window_id: u64) {
+        let mut history = self.focus_history.lock().await;
+        history.retain(|e| e.id != window_id);
+        drop(history);
+
+        self.follow_scopes.lock().await.remove(&window_id);
+        self.origins.lock().await.remove(&window_id);
+        self.staged_normal.lock().await.remove(&window_id);
+        let mut peek = self.peek.lock().await;
+        if peek.is_some_and(|peek| peek.id == window_id) {
+            *peek = None;
         }
+        drop(peek);
+        let was_sticky = self.sticky_windows.lock().await.remove(&window_id);
+        let was_staged = self.staged_set.lock().await.remove(&window_id);
+        if was_sticky || was_staged {
+            self.emit(Event::Closed { id: window_id });
+        }
+    }
+
+    /// Focus history, most recent first
+    pub async fn focus_history(&self) -> Vec<FocusEntry> {
+        let history = self.focus_history.lock().await;
+        history.iter().copied().collect()
     }
 
     /// Add window to sticky list
     pub async fn add_sticky_window(&self, window_id: u64) -> Result<bool> {
         let full_window_list = crate::system_integration::get_full_window_list().await?;
         if !full_window_list.contains(&window_id) {
-            return Err(anyhow::anyhow!("Window not found in Niri"));
+            return Err(crate::protocol::not_found("Window not found in Niri"));
         }
 
         let mut sticky = self.sticky_windows.lock().await;
-        Ok(sticky.insert(window_id))
+        let added = sticky.insert(window_id);
+        if added {
+            self.emit(Event::Sticky { id: window_id });
+        }
+        Ok(added)
     }
 
     /// Remove window from sticky list
     pub async fn remove_sticky_window(&self, window_id: u64) -> Result<bool> {
         let full_window_list = crate::system_integration::get_full_window_list().await?;
         if !full_window_list.contains(&window_id) {
-            return Err(anyhow::anyhow!("Window not found in Niri"));
+            return Err(crate::protocol::not_found("Window not found in Niri"));
         }
 
         let mut sticky = self.sticky_windows.lock().await;
-        Ok(sticky.remove(&window_id))
+        let removed = sticky.remove(&window_id);
+        if removed {
+            self.emit(Event::Unsticky { id: window_id });
+        }
+        Ok(removed)
     }
 
-    /// List all sticky windows
-    pub async fn list_sticky_windows(&self) -> Result<Vec<u64>> {
-        let snapshot: Vec<u64> = {
-            let sticky = self.sticky_windows.lock().await;
-            sticky.iter().copied().collect()
-        };
-        let full_window_list = crate::system_integration::get_full_window_list().await?;
-        let valid_snapshot: Vec<u64> = snapshot
+    /// Add every window matching the selector, as picked by the strategy, to sticky list
+    /// Returns each affected window id with `true` if it was newly added
+    pub async fn add_sticky_matching(
+        &self,
+        selector: &Selector,
+        strategy: MatchStrategy,
+    ) -> Result<Vec<(u64, bool)>> {
+        let ids = self
+            .resolve_matching(selector, strategy, |_| true, "")
+            .await?;
+        let mut sticky = self.sticky_windows.lock().await;
+        Ok(ids
             .into_iter()
-            .filter(|id| full_window_list.contains(id))
-            .collect();
-        Ok(valid_snapshot)
+            .map(|id| {
+                let added = sticky.insert(id);
+                if added {
+                    self.emit(Event::Sticky { id });
+                }
+                (id, added)
+            })
+            .collect())
+    }
+
+    /// Remove every window matching the selector, as picked by the strategy, from sticky list
+    /// Returns each affected window id with `true` if it was in the list
+    pub async fn remove_sticky_matching(
+        &self,
+        selector: &Selector,
+        strategy: MatchStrategy,
+    ) -> Result<Vec<(u64, bool)>> {
+        let ids = self
+            .resolve_matching(selector, strategy, |_| true, "")
+            .await?;
+        let mut sticky = self.sticky_windows.lock().await;
+        Ok(ids
+            .into_iter()
+            .map(|id| {
+                let removed = sticky.remove(&id);
+                if removed {
+                    self.emit(Event::Unsticky { id });
+                }
+                (id, removed)
+            })
+            .collect())
+    }
+
+    /// List all sticky windows, in the order they became sticky
+    pub async fn list_sticky_windows(&self) -> Result<Vec<WindowEntry>> {
+        let snapshot = self.sticky_windows.lock().await.clone();
+        self.describe_windows(&snapshot).await
+    }
+
+    /// List every niri window with its nsticky state, in niri's order
+    pub async fn list_all_windows(&self, selector: Option<&Selector>) -> Result<Vec<WindowEntry>> {
+        let windows = crate::system_integration::get_full_window_info().await?;
+        let workspaces = crate::system_integration::get_workspaces().await?;
+
+        let sticky = self.sticky_windows.lock().await;
+        let staged = self.staged_set.lock().await;
+        Ok(windows
+            .iter()
+            .filter(|window| selector.is_none_or(|sel| sel.matches(window, &workspaces)))
+            .map(|window| describe_window(window, &workspaces, &sticky, &staged))
+            .collect())
+    }
+
+    /// Describe the windows in a set with live niri data, oldest member first
+    /// Windows that no longer exist in niri are skipped
+    async fn describe_windows(&self, set: &WindowSet) -> Result<Vec<WindowEntry>> {
+        let ids: Vec<u64> = set.iter().copied().collect();
+        let windows = crate::system_integration::get_full_window_info().await?;
+        let workspaces = crate::system_integration::get_workspaces().await?;
+
+        let sticky = self.sticky_windows.lock().await;
+        let staged = self.staged_set.lock().await;
+        Ok(ids
+            .into_iter()
+            .filter_map(|id| windows.iter().find(|w| w.id == id))
+            .map(|window| describe_window(window, &workspaces, &sticky, &staged))
+            .collect())
     }
 
     /// Toggle active window sticky status
@@ -61,230 +629,271 @@ impl BusinessLogic {
         let active_id = crate::system_integration::get_active_window_id().await?;
         let full_window_list = crate::system_integration::get_full_window_list().await?;
         if !full_window_list.contains(&active_id) {
-            return Err(anyhow::anyhow!("Active window not found in Niri"));
+            return Err(crate::protocol::not_found(
+                "Active window not found in Niri",
+            ));
         }
 
         let mut sticky = self.sticky_windows.lock().await;
         if sticky.contains(&active_id) {
             sticky.remove(&active_id);
+            self.emit(Event::Unsticky { id: active_id });
             Ok(false) // Removed from sticky
         } else {
             sticky.insert(active_id);
+            self.emit(Event::Sticky { id: active_id });
             Ok(true) // Added to sticky
         }
     }
 
-    /// Toggle window sticky status by app ID
-    /// Cases: window in staged -> move to sticky, window in sticky -> remove from sticky, window in neither -> add to sticky
-    pub async fn toggle_by_appid(&self, appid: &str) -> Result<bool> {
-        let window_id = crate::system_integration::find_window_by_appid(appid).await?;
-        match window_id {
-            Some(id) => {
-                let full_window_list = crate::system_integration::get_full_window_list().await?;
-                if !full_window_list.contains(&id) {
-                    return Err(anyhow::anyhow!(
-                        "Window with appid {} not found in Niri",
-                        appid
-                    ));
-                }
-
-                let sticky = self.sticky_windows.lock().await;
-                let staged = self.staged_set.lock().await;
-
-                if staged.contains(&id) {
-                    drop(sticky);
-                    drop(staged);
-                    let current_ws_id =
-                        crate::system_integration::get_active_workspace_id().await?;
-                    crate::system_integration::move_to_workspace(id, current_ws_id).await?;
-                    let mut sticky = self.sticky_windows.lock().await;
-                    let mut staged = self.staged_set.lock().await;
-                    staged.remove(&id);
-                    sticky.insert(id);
-                    Ok(true)
-                } else if sticky.contains(&id) {
-                    drop(sticky);
-                    drop(staged);
-                    let mut sticky = self.sticky_windows.lock().await;
-                    sticky.remove(&id);
-                    Ok(false)
-                } else {
-                    drop(sticky);
-                    drop(staged);
-                    let current_ws_id =
-                        crate::system_integration::get_active_workspace_id().await?;
-                    crate::system_integration::move_to_workspace(id, current_ws_id).await?;
-                    let mut sticky = self.sticky_windows.lock().await;
-                    sticky.insert(id);
-                    Ok(true)
-                }
-            }
-            None => Err(anyhow::anyhow!("No window found with appid {}", appid)),
+    /// Toggle sticky status of every window matching the selector, as picked by the strategy
+    /// Returns each affected window id with `true` if it is now sticky
+    pub async fn toggle_matching(
+        &self,
+        selector: &Selector,
+        strategy: MatchStrategy,
+    ) -> Result<Vec<(u64, bool)>> {
+        let ids = self
+            .resolve_matching(selector, strategy, |_| true, "")
+            .await?;
+        let mut results = Vec::with_capacity(ids.len());
+        for id in ids {
+            results.push((id, self.toggle_sticky_window(id).await?));
         }
+        Ok(results)
     }
 
-    /// Toggle window sticky status by title
+    /// Toggle window sticky status
     /// Cases: window in staged -> move to sticky, window in sticky -> remove from sticky, window in neither -> add to sticky
-    pub async fn toggle_by_title(&self, title: &str) -> Result<bool> {
-        let window_id = crate::system_integration::find_window_by_title(title).await?;
-        match window_id {
-            Some(id) => {
-                let full_window_list = crate::system_integration::get_full_window_list().await?;
-                if !full_window_list.contains(&id) {
-                    return Err(anyhow::anyhow!(
-                        "Window with title containing '{}' not found in Niri",
-                        title
-                    ));
-                }
+    async fn toggle_sticky_window(&self, id: u64) -> Result<bool> {
+        let sticky = self.sticky_windows.lock().await;
+        let staged = self.staged_set.lock().await;
 
-                let sticky = self.sticky_windows.lock().await;
-                let staged = self.staged_set.lock().await;
-
-                if staged.contains(&id) {
-                    drop(sticky);
-                    drop(staged);
-                    let current_ws_id =
-                        crate::system_integration::get_active_workspace_id().await?;
-                    crate::system_integration::move_to_workspace(id, current_ws_id).await?;
-                    let mut sticky = self.sticky_windows.lock().await;
-                    let mut staged = self.staged_set.lock().await;
-                    staged.remove(&id);
-                    sticky.insert(id);
-                    Ok(true)
-                } else if sticky.contains(&id) {
-                    drop(sticky);
-                    drop(staged);
-                    let mut sticky = self.sticky_windows.lock().await;
-                    sticky.remove(&id);
-                    Ok(false)
-                } else {
-                    drop(sticky);
-                    drop(staged);
-                    let current_ws_id =
-                        crate::system_integration::get_active_workspace_id().await?;
-                    crate::system_integration::move_to_workspace(id, current_ws_id).await?;
-                    let mut sticky = self.sticky_windows.lock().await;
-                    sticky.insert(id);
-                    Ok(true)
-                }
-            }
-            None => Err(anyhow::anyhow!(
-                "No window found with title containing '{}'",
-                title
-            )),
+        if staged.contains(&id) {
+            drop(sticky);
+            drop(staged);
+            let current_ws_id = crate::system_integration::get_active_workspace_id().await?;
+            self.return_window(id, self.destination(current_ws_id, None))
+                .await?;
+            // Toggling sticky makes the window sticky, even if it was minimized as a normal one
+            self.staged_normal.lock().await.remove(&id);
+            Ok(self.mark_unstaged(id).await)
+        } else if sticky.contains(&id) {
+            drop(sticky);
+            drop(staged);
+            let mut sticky = self.sticky_windows.lock().await;
+            sticky.remove(&id);
+            self.emit(Event::Unsticky { id });
+            Ok(false)
+        } else {
+            drop(sticky);
+            drop(staged);
+            let current_ws_id = crate::system_integration::get_active_workspace_id().await?;
+            crate::system_integration::move_to_workspace(id, current_ws_id).await?;
+            let mut sticky = self.sticky_windows.lock().await;
+            sticky.insert(id);
+            self.emit(Event::Sticky { id });
+            Ok(true)
         }
     }
 
-    /// Toggle window stage status by app ID
+    /// Toggle stage status of every window matching the selector, as picked by the strategy
+    /// Returns each affected window id with `true` if it is now staged
+    pub async fn toggle_stage_matching(
+        &self,
+        selector: &Selector,
+        strategy: MatchStrategy,
+        destination: Destination,
+    ) -> Result<Vec<(u64, bool)>> {
+        let ids = self
+            .resolve_matching(selector, strategy, |_| true, "")
+            .await?;
+        let mut results = Vec::with_capacity(ids.len());
+        for id in ids {
+            results.push((id, self.toggle_stage_window(id, destination).await?));
+        }
+        Ok(results)
+    }
+
+    /// Toggle window stage status
     /// Cases: window not in sticky -> error, window in sticky but not staged -> move to staged, window in staged -> move to sticky
-    pub async fn toggle_stage_by_appid(&self, appid: &str, workspace_id: u64) -> Result<()> {
-        let window_id = crate::system_integration::find_window_by_appid(appid).await?;
-        match window_id {
-            Some(id) => {
-                let full_window_list = crate::system_integration::get_full_window_list().await?;
-                if !full_window_list.contains(&id) {
-                    return Err(anyhow::anyhow!(
-                        "Window with appid {} not found in Niri",
-                        appid
-                    ));
-                }
+    async fn toggle_stage_window(&self, id: u64, destination: Destination) -> Result<bool> {
+        let sticky = self.sticky_windows.lock().await;
+        let staged = self.staged_set.lock().await;
 
-                let sticky = self.sticky_windows.lock().await;
-                let staged = self.staged_set.lock().await;
-
-                if !sticky.contains(&id) && !staged.contains(&id) {
-                    drop(sticky);
-                    drop(staged);
-                    Err(anyhow::anyhow!(
-                        "Window with appid {} is not in sticky list",
-                        appid
-                    ))
-                } else if sticky.contains(&id) && !staged.contains(&id) {
-                    drop(sticky);
-                    drop(staged);
-                    crate::system_integration::move_to_named_workspace(id, "stage").await?;
-                    let mut sticky = self.sticky_windows.lock().await;
-                    let mut staged = self.staged_set.lock().await;
-                    sticky.remove(&id);
-                    staged.insert(id);
-                    Ok(())
-                } else if !sticky.contains(&id) && staged.contains(&id) {
-                    drop(sticky);
-                    drop(staged);
-                    crate::system_integration::move_to_workspace(id, workspace_id).await?;
-                    let mut sticky = self.sticky_windows.lock().await;
-                    let mut staged = self.staged_set.lock().await;
-                    staged.remove(&id);
-                    sticky.insert(id);
-                    Ok(())
-                } else {
-                    drop(sticky);
-                    drop(staged);
-                    Err(anyhow::anyhow!(
-                        "Unexpected window state for appid {}",
-                        appid
-                    ))
-                }
-            }
-            None => Err(anyhow::anyhow!("No window found with appid {}", appid)),
+        if !sticky.contains(&id) && !staged.contains(&id) {
+            drop(sticky);
+            drop(staged);
+            Err(crate::protocol::invalid_state(format!(
+                "Window {} is not in sticky list",
+                id
+            )))
+        } else if sticky.contains(&id) && !staged.contains(&id) {
+            drop(sticky);
+            drop(staged);
+            let origins = self.capture_origins(&[id]).await;
+            crate::system_integration::move_to_named_workspace(id, &self.stage_workspace).await?;
+            self.origins.lock().await.extend(origins);
+            let mut sticky = self.sticky_windows.lock().await;
+            let mut staged = self.staged_set.lock().await;
+            sticky.remove(&id);
+            staged.insert(id);
+            self.emit(Event::Staged { id });
+            Ok(true)
+        } else if !sticky.contains(&id) && staged.contains(&id) {
+            drop(sticky);
+            drop(staged);
+            self.return_window(id, destination).await?;
+            self.mark_unstaged(id).await;
+            Ok(false)
+        } else {
+            drop(sticky);
+            drop(staged);
+            Err(crate::protocol::invalid_state(format!(
+                "Unexpected state for window {}",
+                id
+            )))
         }
     }
 
-    /// Toggle window stage status by title
-    /// Cases: window not in sticky -> error, window in sticky but not staged -> move to staged, window in staged -> move to sticky
-    pub async fn toggle_stage_by_title(&self, title: &str, workspace_id: u64) -> Result<()> {
-        let window_id = crate::system_integration::find_window_by_title(title).await?;
-        match window_id {
-            Some(id) => {
-                let full_window_list = crate::system_integration::get_full_window_list().await?;
-                if !full_window_list.contains(&id) {
-                    return Err(anyhow::anyhow!(
-                        "Window with title containing '{}' not found in Niri",
-                        title
-                    ));
-                }
+    /// Windows matching the selector, as picked by the strategy
+    pub async fn matching_windows(
+        &self,
+        selector: &Selector,
+        strategy: MatchStrategy,
+    ) -> Result<Vec<u64>> {
+        self.resolve_matching(selector, strategy, |_| true, "")
+            .await
+    }
 
-                let sticky = self.sticky_windows.lock().await;
-                let staged = self.staged_set.lock().await;
-
-                if !sticky.contains(&id) && !staged.contains(&id) {
-                    drop(sticky);
-                    drop(staged);
-                    Err(anyhow::anyhow!(
-                        "Window with title containing '{}' is not in sticky list",
-                        title
-                    ))
-                } else if sticky.contains(&id) && !staged.contains(&id) {
-                    drop(sticky);
-                    drop(staged);
-                    crate::system_integration::move_to_named_workspace(id, "stage").await?;
-                    let mut sticky = self.sticky_windows.lock().await;
-                    let mut staged = self.staged_set.lock().await;
-                    sticky.remove(&id);
-                    staged.insert(id);
-                    Ok(())
-                } else if !sticky.contains(&id) && staged.contains(&id) {
-                    drop(sticky);
-                    drop(staged);
-                    crate::system_integration::move_to_workspace(id, workspace_id).await?;
-                    let mut sticky = self.sticky_windows.lock().await;
-                    let mut staged = self.staged_set.lock().await;
-                    staged.remove(&id);
-                    sticky.insert(id);
-                    Ok(())
-                } else {
-                    drop(sticky);
-                    drop(staged);
-                    Err(anyhow::anyhow!(
-                        "Unexpected window state for title containing '{}'",
-                        title
-                    ))
-                }
-            }
-            None => Err(anyhow::anyhow!(
-                "No window found with title containing '{}'",
-                title
-            )),
+    /// Set or, with `None`, clear the follow scope of windows
+    pub async fn set_follow_scope(&self, ids: &[u64], scope: Option<FollowScope>) -> Result<()> {
+        let full_window_list = crate::system_integration::get_full_window_list().await?;
+        if let Some(missing) = ids.iter().find(|id| !full_window_list.contains(id)) {
+            return Err(crate::protocol::not_found(format!(
+                "Window {missing} not found in Niri"
+            )));
+        }
+
+        let mut scopes = self.follow_scopes.lock().await;
+        for id in ids {
+            match &scope {
+                Some(scope) => scopes.insert(*id, scope.clone()),
+                None => scopes.remove(id),
+            };
+        }
+        Ok(())
+    }
+
+    /// Effective follow scope of each window, with the rule it came from if not set directly
+    pub async fn follow_scopes(
+        &self,
+        ids: &[u64],
+    ) -> Result<Vec<(u64, FollowScope, Option<String>)>> {
+        let windows = crate::system_integration::get_full_window_info().await?;
+        let workspaces = crate::system_integration::get_workspaces().await?;
+        let scopes = self.follow_scopes.lock().await;
+        ids.iter()
+            .map(|id| {
+                let window = windows.iter().find(|w| w.id == *id).ok_or_else(|| {
+                    crate::protocol::not_found(format!("Window {id} not found in Niri"))
+                })?;
+                Ok(match scopes.get(id) {
+                    Some(scope) => (*id, scope.clone(), None),
+                    None => match self.follow_rule(window, &workspaces) {
+                        Some((selector, scope)) => (*id, scope.clone(), Some(selector.to_string())),
+                        None => (*id, FollowScope::default(), None),
+                    },
+                })
+            })
+            .collect()
+    }
+
+    /// Whether sticky windows must stay off this workspace
+    fn is_reserved(&self, workspace: &WorkspaceInfo) -> bool {
+        workspace.name.as_deref() == Some(self.stage_workspace.as_str())
+            || self
+                .reserved_workspaces
+                .iter()
+                .any(|p| p.matches(workspace))
+    }
+
+    /// First config rule matching the window
+    fn follow_rule(
+        &self,
+        window: &WindowInfo,
+        workspaces: &[WorkspaceInfo],
+    ) -> Option<&(Selector, FollowScope)> {
+        self.follow_rules
+            .iter()
+            .find(|(selector, _)| selector.matches(window, workspaces))
+    }
+
+    /// Resolve the windows matching a selector that pass `eligible`, narrowed by the strategy
+    /// Errors name the selector, and the matching windows with `ineligible_reason` if none were eligible
+    async fn resolve_matching(
+        &self,
+        selector: &Selector,
+        strategy: MatchStrategy,
+        eligible: impl Fn(u64) -> bool,
+        ineligible_reason: &str,
+    ) -> Result<Vec<u64>> {
+        let matches = crate::system_integration::find_windows(selector).await?;
+        if matches.is_empty() {
+            return Err(crate::protocol::not_found(format!(
+                "No window found matching {}",
+                selector
+            )));
+        }
+
+        let (eligible_matches, ineligible): (Vec<_>, Vec<_>) =
+            matches.into_iter().partition(|w| eligible(w.id));
+        if eligible_matches.is_empty() {
+            let ids: Vec<u64> = ineligible.iter().map(|w| w.id).collect();
+            return Err(crate::protocol::invalid_state(format!(
+                "Windows matching {} {:?} {}",
+                selector, ids, ineligible_reason
+            )));
+        }
+
+        let ids = {
+            let history = self.focus_history.lock().await;
+            select_windows(eligible_matches, strategy, &history)
+        };
+        if ids.is_empty() {
+            return Err(crate::protocol::not_found(format!(
+                "No {} window found matching {}",
+                strategy, selector
+            )));
+        }
+        Ok(ids)
+    }
+
+    /// Resolve a selector to the one staged window a command acts on
+    /// Several matches, e.g. with `--all`, are rejected rather than picking one
+    async fn resolve_staged_window(
+        &self,
+        selector: &Selector,
+        strategy: MatchStrategy,
+        action: &str,
+    ) -> Result<u64> {
+        let staged = self.staged_set.lock().await.clone();
+        let ids = self
+            .resolve_matching(
+                selector,
+                strategy,
+                |id| staged.contains(&id),
+                &format!("are not in staged list, cannot {action}"),
+            )
+            .await?;
+        match ids[..] {
+            [id] => Ok(id),
+            _ => Err(crate::protocol::invalid_request(format!(
+                "Selector {} matches {} staged windows {:?}, cannot {action} more than one",
+                selector,
+                ids.len(),
+                ids
+            ))),
         }
     }
 
@@ -293,7 +902,7 @@ impl BusinessLogic {
     pub async fn stage_window(&self, window_id: u64) -> Result<()> {
         let full_window_list = crate::system_integration::get_full_window_list().await?;
         if !full_window_list.contains(&window_id) {
-            return Err(anyhow::anyhow!("Window not found in Niri"));
+            return Err(crate::protocol::not_found("Window not found in Niri"));
         }
 
         let sticky = self.sticky_windows.lock().await;
@@ -302,35 +911,59 @@ impl BusinessLogic {
         if staged.contains(&window_id) {
             drop(sticky);
             drop(staged);
-            return Err(anyhow::anyhow!("Window is already in staged list"));
+            return Err(crate::protocol::invalid_state(
+                "Window is already in staged list",
+            ));
         }
 
         let was_sticky = sticky.contains(&window_id);
         if was_sticky {
             drop(sticky);
             drop(staged);
-            if let Err(e) =
-                crate::system_integration::move_to_named_workspace(window_id, "stage").await
-            {
-                let mut sticky = self.sticky_windows.lock().await;
-                sticky.insert(window_id);
-                return Err(e);
-            }
+            let origins = self.capture_origins(&[window_id]).await;
+            crate::system_integration::move_to_named_workspace(window_id, &self.stage_workspace)
+                .await?;
+            self.origins.lock().await.extend(origins);
 
             let mut sticky = self.sticky_windows.lock().await;
             let mut staged = self.staged_set.lock().await;
             sticky.remove(&window_id);
             staged.insert(window_id);
+            self.emit(Event::Staged { id: window_id });
             Ok(())
         } else {
             drop(sticky);
             drop(staged);
-            Err(anyhow::anyhow!(
-                "Window is not in sticky list, cannot stage"
+            Err(crate::protocol::invalid_state(
+                "Window is not in sticky list, cannot stage",
             ))
         }
     }
 
+    /// Move sticky windows matching the selector to the stage workspace
+    /// Only sticky windows are considered, then narrowed by the strategy
+    /// Returns the ids of the windows that were staged
+    pub async fn stage_matching(
+        &self,
+        selector: &Selector,
+        strategy: MatchStrategy,
+    ) -> Result<Vec<u64>> {
+        let sticky = self.sticky_windows.lock().await.clone();
+        let ids = self
+            .resolve_matching(
+                selector,
+                strategy,
+                |id| sticky.contains(&id),
+                "are not in sticky list, cannot stage",
+            )
+            .await?;
+
+        for id in &ids {
+            self.stage_window(*id).await?;
+        }
+        Ok(ids)
+    }
+
     /// Move the active sticky window to the stage workspace
     /// Cases: window not in sticky -> error, window already staged -> error, window in sticky -> move to stage
     pub async fn stage_active_window(&self) -> Result<()> {
@@ -338,7 +971,9 @@ impl BusinessLogic {
 
         let full_window_list = crate::system_integration::get_full_window_list().await?;
         if !full_window_list.contains(&id) {
-            return Err(anyhow::anyhow!("Active window not found in Niri"));
+            return Err(crate::protocol::not_found(
+                "Active window not found in Niri",
+            ));
         }
 
         let sticky = self.sticky_windows.lock().await;
@@ -347,33 +982,398 @@ impl BusinessLogic {
         if staged.contains(&id) {
             drop(sticky);
             drop(staged);
-            return Err(anyhow::anyhow!("Window is already in staged list"));
+            return Err(crate::protocol::invalid_state(
+                "Window is already in staged list",
+            ));
         }
 
         let was_sticky = sticky.contains(&id);
         if was_sticky {
             drop(sticky);
             drop(staged);
-            if let Err(e) = crate::system_integration::move_to_named_workspace(id, "stage").await {
-                let mut sticky = self.sticky_windows.lock().await;
-                sticky.insert(id);
-                return Err(e);
-            }
+            let origins = self.capture_origins(&[id]).await;
+            crate::system_integration::move_to_named_workspace(id, &self.stage_workspace).await?;
+            self.origins.lock().await.extend(origins);
 
             let mut sticky = self.sticky_windows.lock().await;
             let mut staged = self.staged_set.lock().await;
             sticky.remove(&id);
             staged.insert(id);
+            self.emit(Event::Staged { id });
             Ok(())
         } else {
             drop(sticky);
             drop(staged);
-            Err(anyhow::anyhow!(
-                "Window is not in sticky list, cannot stage"
+            Err(crate::protocol::invalid_state(
+                "Window is not in sticky list, cannot stage",
             ))
         }
     }
 
+    /// Where each window is now, to return it there after staging
+    /// Windows already on the stage workspace have no origin to remember
+    async fn capture_origins(&self, ids: &[u64]) -> HashMap<u64, WindowOrigin> {
+        let (windows, workspaces) = match tokio::try_join!(
+            crate::system_integration::get_full_window_info(),
+            crate::system_integration::get_workspaces()
+        ) {
+            Ok(info) => info,
+            Err(e) => {
+                tracing::warn!("Failed to record where windows are staged from: {e:#}");
+                return HashMap::new();
+            }
+        };
+        ids.iter()
+            .filter_map(|id| {
+                let window = windows.iter().find(|w| w.id == *id)?;
+                let workspace = workspaces
+                    .iter()
+                    .find(|ws| Some(ws.id) == window.workspace_id)
+                    .filter(|ws| ws.name.as_deref() != Some(self.stage_workspace.as_str()))?;
+                let layout = window.layout;
+                let floating = layout.filter(|_| window.is_floating).and_then(|layout| {
+                    let (x, y) = layout.position?;
+                    Some(FloatingGeometry {
+                        x,
+                        y,
+                        width: layout.size.0,
+                        height: layout.size.1,
+                    })
+                });
+                let origin = WindowOrigin {
+                    workspace_id: workspace.id,
+                    workspace_idx: workspace.idx,
+                    workspace_name: workspace.name.clone(),
+                    output: workspace.output.clone(),
+                    column: layout.and_then(|layout| layout.column),
+                    was_floating: window.is_floating,
+                    floating,
+                };
+                Some((*id, origin))
+            })
+            .collect()
+    }
+
+    /// Take a window that has left the stage out of the staged list
+    /// It is sticky again unless it was a normal window when it was staged;
+    /// returns whether it is sticky
+    async fn mark_unstaged(&self, id: u64) -> bool {
+        let mut peek = self.peek.lock().await;
+        if peek.is_some_and(|peek| peek.id == id) {
+            *peek = None;
+        }
+        drop(peek);
+        let mut sticky = self.sticky_windows.lock().await;
+        let mut staged = self.staged_set.lock().await;
+        staged.remove(&id);
+        let is_sticky = !self.staged_normal.lock().await.remove(&id);
+        if is_sticky {
+            sticky.insert(id);
+        }
+        self.emit(Event::Unstaged {
+            id,
+            sticky: is_sticky,
+        });
+        is_sticky
+    }
+
+    /// Move any window to the stage workspace, remembering whether it was sticky
+    /// Cases: window already staged -> error, window sticky or normal -> move to stage
+    pub async fn minimize_window(&self, window_id: u64) -> Result<()> {
+        let full_window_list = crate::system_integration::get_full_window_list().await?;
+        if !full_window_list.contains(&window_id) {
+            return Err(crate::protocol::not_found("Window not found in Niri"));
+        }
+        if self.staged_set.lock().await.contains(&window_id) {
+            return Err(crate::protocol::invalid_state(
+                "Window is already in staged list",
+            ));
+        }
+
+        let origins = self.capture_origins(&[window_id]).await;
+        crate::system_integration::move_to_named_workspace(window_id, &self.stage_workspace)
+            .await?;
+        self.origins.lock().await.extend(origins);
+
+        let mut sticky = self.sticky_windows.lock().await;
+        let mut staged = self.staged_set.lock().await;
+        if !sticky.remove(&window_id) {
+            self.staged_normal.lock().await.insert(window_id);
+        }
+        staged.insert(window_id);
+        self.emit(Event::Staged { id: window_id });
+        Ok(())
+    }
+
+    /// Minimize the windows matching the selector that are not staged yet
+    /// Returns the ids of the windows that were staged
+    pub async fn minimize_matching(
+        &self,
+        selector: &Selector,
+        strategy: MatchStrategy,
+    ) -> Result<Vec<u64>> {
+        let staged = self.staged_set.lock().await.clone();
+        let ids = self
+            .resolve_matching(
+                selector,
+                strategy,
+                |id| !staged.contains(&id),
+                "are already in staged list",
+            )
+            .await?;
+
+        for id in &ids {
+            self.minimize_window(*id).await?;
+        }
+        Ok(ids)
+    }
+
+    /// Bring the next staged window onto the workspace, sending the one shown before back
+    /// The cycle starts over, at the most recently staged window (or the oldest
+    /// with `reverse`), whenever the stage changed outside of it
+    /// Returns the window now shown, `None` if the stage is empty
+    pub async fn cycle_stage(&self, reverse: bool, workspace_id: u64) -> Result<Option<u64>> {
+        let full_window_list = crate::system_integration::get_full_window_list().await?;
+        let staged: Vec<u64> = {
+            let staged = self.staged_set.lock().await;
+            staged
+                .iter()
+                .rev()
+                .copied()
+                .filter(|id| full_window_list.contains(id))
+                .collect()
+        };
+
+        let mut cycle = self.stage_cycle.lock().await;
+        let shown_origin = cycle.shown_origin.take();
+        let Some(step) = cycle.advance(staged, |id| full_window_list.contains(&id), reverse) else {
+            return Ok(None);
+        };
+        if cycle.shown == Some(step.show) {
+            cycle.shown_origin = shown_origin;
+            return Ok(Some(step.show));
+        }
+        if let Some(back) = step.send_back {
+            if let Err(e) = self.minimize_window(back).await {
+                cycle.shown_origin = shown_origin;
+                return Err(e);
+            }
+            if let Some(origin) = shown_origin {
+                self.origins.lock().await.insert(back, origin);
+            }
+        }
+        cycle.shown = None;
+        let origin = self.origins.lock().await.get(&step.show).cloned();
+        self.unstage_window(step.show, Destination::Workspace(workspace_id))
+            .await?;
+        cycle.shown = Some(step.show);
+        cycle.shown_origin = origin;
+        Ok(Some(step.show))
+    }
+
+    /// Show a staged window matching the selector on the workspace, focused, until
+    /// it loses focus, the workspace changes or the timeout passes
+    /// The window stays staged throughout; `timeout` overrides the configured one
+    /// Returns the id of the window shown
+    pub async fn peek_matching(
+        &self,
+        selector: &Selector,
+        strategy: MatchStrategy,
+        workspace_id: u64,
+        timeout: Option<Duration>,
+    ) -> Result<u64> {
+        // Only one window can hold focus, so only one is shown
+        let id = self
+            .resolve_staged_window(selector, strategy, "peek at")
+            .await?;
+
+        self.end_peek().await;
+        crate::system_integration::move_to_workspace(id, workspace_id).await?;
+        let started = Instant::now();
+        *self.peek.lock().await = Some(Peek {
+            id,
+            workspace_id,
+            focused: false,
+            started,
+        });
+        if let Err(e) = crate::system_integration::focus_window(id).await {
+            self.end_peek().await;
+            return Err(e);
+        }
+
+        if let Some(timeout) = timeout.or(self.peek_timeout) {
+            let business_logic = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(timeout).await;
+                business_logic
+                    .finish_peek("timed out", |peek| peek.started == started)
+                    .await;
+            });
+        }
+        Ok(id)
+    }
+
+    /// Note a focus change from niri, ending a peek whose window lost focus
+    pub async fn handle_focus_change(&self, window_id: Option<u64>) {
+        self.finish_peek("lost focus", |peek| {
+            if window_id == Some(peek.id) {
+                peek.focused = true;
+            }
+            peek.focused && window_id != Some(peek.id)
+        })
+        .await;
+    }
+
+    /// Send a peeked window back to the stage
+    pub async fn end_peek(&self) {
+        self.finish_peek("ended", |_| true).await;
+    }
+
+    /// End the peek if `ends` says so, moving the window back unless it was unstaged meanwhile
+    async fn finish_peek(&self, reason: &str, ends: impl FnOnce(&mut Peek) -> bool) {
+        let peek = {
+            let mut current = self.peek.lock().await;
+            let ended = current.as_mut().is_some_and(ends);
+            if ended { current.take() } else { None }
+        };
+        let Some(peek) = peek else {
+            return;
+        };
+        if !self.staged_set.lock().await.contains(&peek.id) {
+            return;
+        }
+        tracing::debug!(
+            "Peek at window {} {reason}, returning it to the stage",
+            peek.id
+        );
+        if let Err(e) =
+            crate::system_integration::move_to_named_workspace(peek.id, &self.stage_workspace).await
+        {
+            tracing::warn!("Failed to return peeked window {}: {e:#}", peek.id);
+            self.emit(Event::Error {
+                message: format!("Failed to return peeked window {}: {e:#}", peek.id),
+            });
+        }
+    }
+
+    /// Send the focused sticky window to the stage and bring a staged one onto the workspace
+    /// Without a selector the most recently staged window comes back. Both moves
+    /// happen before any state changes, and the first is undone if the second fails
+    /// Returns the ids of the window staged and the window brought back
+    pub async fn swap_stage(
+        &self,
+        selector: Option<(&Selector, MatchStrategy)>,
+        workspace_id: u64,
+    ) -> Result<(u64, u64)> {
+        let outgoing = crate::system_integration::get_active_window_id().await?;
+        if !self.sticky_windows.lock().await.contains(&outgoing) {
+            return Err(crate::protocol::invalid_state(format!(
+                "Focused window {outgoing} is not in sticky list, cannot swap"
+            )));
+        }
+        let incoming = match selector {
+            Some((selector, strategy)) => {
+                self.resolve_staged_window(selector, strategy, "swap")
+                    .await?
+            }
+            None => self
+                .stage_top(false)
+                .await?
+                .ok_or_else(|| crate::protocol::invalid_state("No staged windows to swap in"))?,
+        };
+
+        let origins = self.capture_origins(&[outgoing]).await;
+        exchange_windows(incoming, outgoing, workspace_id, &self.stage_workspace).await?;
+
+        self.origins.lock().await.remove(&incoming);
+        self.mark_unstaged(incoming).await;
+        self.origins.lock().await.extend(origins);
+        let mut sticky = self.sticky_windows.lock().await;
+        let mut staged = self.staged_set.lock().await;
+        sticky.remove(&outgoing);
+        staged.insert(outgoing);
+        self.emit(Event::Staged { id: outgoing });
+        Ok((outgoing, incoming))
+    }
+
+    /// Move a window off the stage, forgetting its origin once it has left
+    async fn return_window(&self, id: u64, destination: Destination) -> Result<()> {
+        self.move_off_stage(id, destination).await?;
+        self.origins.lock().await.remove(&id);
+        Ok(())
+    }
+
+    async fn move_off_stage(&self, id: u64, destination: Destination) -> Result<()> {
+        let fallback = match destination {
+            Destination::Workspace(ws_id) => {
+                return crate::system_integration::move_to_workspace(id, ws_id).await;
+            }
+            Destination::Origin { fallback } => fallback,
+        };
+        let Some(origin) = self.origins.lock().await.get(&id).cloned() else {
+            return crate::system_integration::move_to_workspace(id, fallback).await;
+        };
+
+        // Named workspaces keep their name when niri recreates them with a new id
+        let workspaces = crate::system_integration::get_workspaces().await?;
+        let workspace = workspaces
+            .iter()
+            .find(|ws| ws.id == origin.workspace_id)
+            .or_else(|| {
+                let name = origin.workspace_name.as_deref()?;
+                workspaces
+                    .iter()
+                    .find(|ws| ws.name.as_deref() == Some(name))
+            });
+        let Some(workspace) = workspace else {
+            tracing::debug!(
+                "Origin workspace {} of window {id} is gone, returning it to workspace {fallback}",
+                origin.workspace_id
+            );
+            return crate::system_integration::move_to_workspace(id, fallback).await;
+        };
+
+        crate::system_integration::move_to_workspace(id, workspace.id).await?;
+        if let Err(e) = self
+            .restore_layout(id, &origin, workspace.id == fallback)
+            .await
+        {
+            tracing::warn!("Failed to restore the position of window {id}: {e:#}");
+        }
+        Ok(())
+    }
+
+    /// Put a window back into its origin column, or its floating position and size
+    /// Moving the column needs the window focused, so focus then goes back to the
+    /// previous window; neither focus change counts as the user's
+    async fn restore_layout(&self, id: u64, origin: &WindowOrigin, on_screen: bool) -> Result<()> {
+        let windows = crate::system_integration::get_full_window_info().await?;
+        let Some(window) = windows.iter().find(|w| w.id == id) else {
+            return Ok(());
+        };
+
+        let plan = plan_layout(origin, window.is_floating, on_screen);
+        if let Some(floating) = plan.set_floating {
+            crate::system_integration::set_floating(id, floating).await?;
+        }
+        if let Some(geometry) = plan.place {
+            crate::system_integration::place_floating_window(id, geometry).await?;
+        }
+        if let Some(column) = plan.column {
+            let focused = windows.iter().find(|w| w.is_focused).map(|w| w.id);
+            if focused != Some(id) {
+                self.focus_quietly(id).await?;
+            }
+            crate::system_integration::move_column_to_index(column).await?;
+            if let Some(focused) = focused
+                && focused != id
+            {
+                self.focus_quietly(focused).await?;
+            }
+        }
+        Ok(())
+    }
+
     /// Check if window is staged
     pub async fn is_window_staged(&self, window_id: u64) -> bool {
         let staged = self.staged_set.lock().await;
@@ -397,43 +1397,83 @@ impl BusinessLogic {
 
         let full_window_list = crate::system_integration::get_full_window_list().await?;
         let valid_sticky_ids: Vec<u64> = sticky_ids
-            .into_iter()
+            .iter()
+            .copied()
             .filter(|id| full_window_list.contains(id))
             .collect();
 
+        let mut origins = self.capture_origins(&valid_sticky_ids).await;
         for id in valid_sticky_ids {
-            if crate::system_integration::move_to_named_workspace(id, "stage")
+            if crate::system_integration::move_to_named_workspace(id, &self.stage_workspace)
                 .await
                 .is_ok()
             {
                 successfully_staged.push(id);
             } else {
-                eprintln!("Failed to move window {} to stage", id);
+                tracing::warn!("Failed to move window {id} to {}", self.stage_workspace);
             }
         }
 
+        origins.retain(|id, _| successfully_staged.contains(id));
+        self.origins.lock().await.extend(origins);
         let mut sticky = self.sticky_windows.lock().await;
         let mut staged = self.staged_set.lock().await;
         for id in &successfully_staged {
             sticky.remove(id);
             staged.insert(*id);
+            self.emit(Event::Staged { id: *id });
         }
 
         Ok(successfully_staged.len())
     }
 
-    /// List all staged windows
-    pub async fn list_staged_windows(&self) -> Result<Vec<u64>> {
+    /// List all staged windows, most recently staged first
+    pub async fn list_staged_windows(&self) -> Result<Vec<WindowEntry>> {
+        let snapshot = self.staged_set.lock().await.clone();
+        let mut windows = self.describe_windows(&snapshot).await?;
+        windows.reverse();
+        Ok(windows)
+    }
+
+    /// The most recently staged window that still exists, or the oldest
+    async fn stage_top(&self, oldest: bool) -> Result<Option<u64>> {
+        let full_window_list = crate::system_integration::get_full_window_list().await?;
         let staged = self.staged_set.lock().await;
-        Ok(staged.iter().copied().collect())
+        let mut ids = staged.iter().filter(|id| full_window_list.contains(id));
+        Ok(if oldest { ids.next() } else { ids.next_back() }.copied())
+    }
+
+    /// The staged window `pop_staged` would bring back, `None` if the stage is empty
+    pub async fn peek_staged(&self, oldest: bool) -> Result<Option<WindowEntry>> {
+        let Some(id) = self.stage_top(oldest).await? else {
+            return Ok(None);
+        };
+        let mut top = WindowSet::default();
+        top.insert(id);
+        Ok(self.describe_windows(&top).await?.pop())
+    }
+
+    /// Unstage the most recently staged window, or the oldest
+    /// Returns its id and whether it is sticky again, `None` if the stage is empty
+    pub async fn pop_staged(
+        &self,
+        oldest: bool,
+        destination: Destination,
+    ) -> Result<Option<(u64, bool)>> {
+        let Some(id) = self.stage_top(oldest).await? else {
+            return Ok(None);
+        };
+        let sticky = self.unstage_window(id, destination).await?;
+        Ok(Some((id, sticky)))
     }
 
     /// Move a staged window back to sticky and current workspace
     /// Cases: window already sticky -> error, window not staged -> error, window staged -> move to sticky
-    pub async fn unstage_window(&self, window_id: u64, workspace_id: u64) -> Result<()> {
+    /// Returns whether the window is sticky again, not a normal window it was minimized as
+    pub async fn unstage_window(&self, window_id: u64, destination: Destination) -> Result<bool> {
         let full_window_list = crate::system_integration::get_full_window_list().await?;
         if !full_window_list.contains(&window_id) {
-            return Err(anyhow::anyhow!("Window not found in Niri"));
+            return Err(crate::protocol::not_found("Window not found in Niri"));
         }
 
         let sticky = self.sticky_windows.lock().await;
@@ -442,44 +1482,37 @@ impl BusinessLogic {
         if sticky.contains(&window_id) {
             drop(sticky);
             drop(staged);
-            return Err(anyhow::anyhow!("Window is already in sticky list"));
+            return Err(crate::protocol::invalid_state(
+                "Window is already in sticky list",
+            ));
         }
 
         let was_staged = staged.contains(&window_id);
         if was_staged {
             drop(sticky);
             drop(staged);
-            if let Err(e) =
-                crate::system_integration::move_to_workspace(window_id, workspace_id).await
-            {
-                let mut staged = self.staged_set.lock().await;
-                staged.insert(window_id);
-                return Err(e);
-            }
-
-            let mut staged = self.staged_set.lock().await;
-            let mut sticky = self.sticky_windows.lock().await;
-            staged.remove(&window_id);
-            sticky.insert(window_id);
-
-            Ok(())
+            self.return_window(window_id, destination).await?;
+            Ok(self.mark_unstaged(window_id).await)
         } else {
             drop(sticky);
             drop(staged);
-            Err(anyhow::anyhow!(
-                "Window is not in staged list, cannot unstage"
+            Err(crate::protocol::invalid_state(
+                "Window is not in staged list, cannot unstage",
             ))
         }
     }
 
     /// Move the active staged window back to sticky and current workspace
     /// Cases: window already sticky -> error, window not staged -> error, window staged -> move to sticky
-    pub async fn unstage_active_window(&self, workspace_id: u64) -> Result<()> {
+    /// Returns whether the window is sticky again
+    pub async fn unstage_active_window(&self, destination: Destination) -> Result<bool> {
         let id = crate::system_integration::get_active_window_id().await?;
 
         let full_window_list = crate::system_integration::get_full_window_list().await?;
         if !full_window_list.contains(&id) {
-            return Err(anyhow::anyhow!("Active window not found in Niri"));
+            return Err(crate::protocol::not_found(
+                "Active window not found in Niri",
+            ));
         }
 
         let sticky = self.sticky_windows.lock().await;
@@ -488,36 +1521,54 @@ impl BusinessLogic {
         if sticky.contains(&id) {
             drop(sticky);
             drop(staged);
-            return Err(anyhow::anyhow!("Window is already in sticky list"));
+            return Err(crate::protocol::invalid_state(
+                "Window is already in sticky list",
+            ));
         }
 
         let was_staged = staged.contains(&id);
         if was_staged {
             drop(sticky);
             drop(staged);
-            if let Err(e) = crate::system_integration::move_to_workspace(id, workspace_id).await {
-                let mut staged = self.staged_set.lock().await;
-                staged.insert(id);
-                return Err(e);
-            }
-
-            let mut staged = self.staged_set.lock().await;
-            let mut sticky = self.sticky_windows.lock().await;
-            staged.remove(&id);
-            sticky.insert(id);
-
-            Ok(())
+            self.return_window(id, destination).await?;
+            Ok(self.mark_unstaged(id).await)
         } else {
             drop(sticky);
             drop(staged);
-            Err(anyhow::anyhow!(
-                "Window is not in staged list, cannot unstage"
+            Err(crate::protocol::invalid_state(
+                "Window is not in staged list, cannot unstage",
             ))
         }
     }
 
+    /// Move staged windows matching the selector back to sticky and current workspace
+    /// Only staged windows are considered, then narrowed by the strategy
+    /// Returns the ids of the windows that were unstaged and whether each is sticky again
+    pub async fn unstage_matching(
+        &self,
+        selector: &Selector,
+        strategy: MatchStrategy,
+        destination: Destination,
+    ) -> Result<Vec<(u64, bool)>> {
+        let staged = self.staged_set.lock().await.clone();
+        let ids = self
+            .resolve_matching(
+                selector,
+                strategy,
+                |id| staged.contains(&id),
+                "are not in staged list, cannot unstage",
+            )
+            .await?;
+
+        let mut unstaged = Vec::with_capacity(ids.len());
+        for id in ids {
+            unstaged.push((id, self.unstage_window(id, destination).await?));
+        }
+        Ok(unstaged)
+    }
+
     /// Unstage all staged windows
-    pub async fn unstage_all_windows(&self, workspace_id: u64) -> Result<usize> {
+    pub async fn unstage_all_windows(&self, destination: Destination) -> Result<usize> {
         let ids_to_unstage: Vec<u64> = {
             let staged = self.staged_set.lock().await;
             if staged.is_empty() {
@@ -534,46 +1585,771 @@ impl BusinessLogic {
 
         let mut successfully_unstaged = Vec::new();
         for id in &valid_ids_to_unstage {
-            if crate::system_integration::move_to_workspace(*id, workspace_id)
-                .await
-                .is_ok()
-            {
-                successfully_unstaged.push(*id);
-            } else {
-                eprintln!("Failed to move window {} to workspace {}", id, workspace_id);
+            match self.return_window(*id, destination).await {
+                Ok(()) => successfully_unstaged.push(*id),
+                Err(e) => tracing::warn!("Failed to move window {id} off the stage: {e:#}"),
             }
         }
 
-        let mut staged = self.staged_set.lock().await;
-        let mut sticky = self.sticky_windows.lock().await;
         for id in &successfully_unstaged {
-            staged.remove(id);
-            sticky.insert(*id);
+            self.mark_unstaged(*id).await;
         }
 
         Ok(successfully_unstaged.len())
     }
 
     /// Handle workspace activation by moving sticky windows to new workspace
+    /// Windows whose follow scope excludes the workspace stay where they are
     pub async fn handle_workspace_activation(&self, ws_id: u64) -> Result<()> {
-        // Update sticky window list, removing non-existent windows
-        let sticky_snapshot = {
-            let mut sticky = self.sticky_windows.lock().await;
-            let full_window_list = crate::system_integration::get_full_window_list()
-                .await
-                .unwrap_or_default();
-            sticky.retain(|win_id| full_window_list.contains(win_id));
-            println!("Updated sticky windows: {:?}", *sticky);
-            sticky.clone()
+        self.finish_peek("workspace changed", |peek| peek.workspace_id != ws_id)
+            .await;
+        // A failed query must not look like every window closed
+        let windows = crate::system_integration::get_full_window_info().await?;
+        let workspaces = crate::system_integration::get_workspaces().await?;
+
+        // Forget sticky windows that no longer exist, with their scopes and origins
+        let vanished: Vec<u64> = {
+            let sticky = self.sticky_windows.lock().await;
+            sticky
+                .iter()
+                .copied()
+                .filter(|id| !windows.iter().any(|w| w.id == *id))
+                .collect()
         };
+        for id in vanished {
+            self.forget_window(id).await;
+        }
+        let sticky_snapshot = self.sticky_windows.lock().await.clone();
+        tracing::debug!(
+            "Updated sticky windows: {:?}",
+            sticky_snapshot.iter().collect::<Vec<_>>()
+        );
 
         // Move sticky windows to new workspace
+        let target = workspaces.iter().find(|ws| ws.id == ws_id);
+        // Following into the stage would mix sticky windows with stashed ones
+        if let Some(workspace) = target
+            && self.is_reserved(workspace)
+        {
+            tracing::debug!("Workspace {ws_id} is reserved, sticky windows stay where they are");
+            return Ok(());
+        }
+        let excluded: HashSet<u64> = {
+            let scopes = self.follow_scopes.lock().await;
+            sticky_snapshot
+                .iter()
+                .copied()
+                .filter(|id| {
+                    let scope = scopes.get(id).or_else(|| {
+                        let window = windows.iter().find(|w| w.id == *id)?;
+                        self.follow_rule(window, &workspaces)
+                            .map(|(_, scope)| scope)
+                    });
+                    scope.is_some_and(|scope| {
+                        !scope.is_unrestricted() && target.is_none_or(|ws| !scope.allows(ws))
+                    })
+                })
+                .collect()
+        };
+        let mut moved = Vec::new();
         for win_id in sticky_snapshot.iter() {
-            if let Err(_e) = crate::system_integration::move_to_workspace(*win_id, ws_id).await {
-                eprintln!("Failed to move window {}: {:?}", win_id, _e);
+            if excluded.contains(win_id) {
+                tracing::debug!(
+                    "Window {win_id} stays, its follow scope excludes workspace {ws_id}"
+                );
+                continue;
+            }
+            match crate::system_integration::move_to_workspace(*win_id, ws_id).await {
+                Ok(()) => moved.push(*win_id),
+                Err(e) => {
+                    tracing::warn!("Failed to move window {win_id}: {e:#}");
+                    self.emit(Event::Error {
+                        message: format!("Failed to move window {win_id}: {e:#}"),
+                    });
+                }
             }
         }
+        self.emit(Event::FollowCompleted {
+            workspace_id: ws_id,
+            windows: moved,
+        });
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    /// `NIRI_SOCKET` and `PATH` are process-wide, so tests talking to a fake
+    /// niri take turns
+    static FAKE_NIRI: Mutex<()> = Mutex::const_new(());
+
+    /// A niri that runs actions on a throwaway socket and answers `niri msg`
+    /// queries through a script on `PATH` reading files next to the socket
+    struct FakeNiri {
+        dir: std::path::PathBuf,
+        state: Arc<std::sync::Mutex<FakeState>>,
+    }
+
+    #[derive(Default)]
+    struct FakeState {
+        windows: Vec<Value>,
+        workspaces: Vec<Value>,
+        actions: Vec<Value>,
+    }
+
+    impl FakeState {
+        /// Apply the actions that change what queries report
+        fn apply(&mut self, action: &Value) {
+            if let Some(args) = action.get("MoveWindowToWorkspace") {
+                let reference = &args["reference"];
+                let workspace = self
+                    .workspaces
+                    .iter()
+                    .find(|ws| ws["id"] == reference["Id"] || ws["name"] == reference["Name"]);
+                if let Some(ws_id) = workspace.map(|ws| ws["id"].clone())
+                    && let Some(window) = self
+                        .windows
+                        .iter_mut()
+                        .find(|w| w["id"] == args["window_id"])
+                {
+                    window["workspace_id"] = ws_id;
+                }
+            } else if let Some(args) = action.get("FocusWindow") {
+                for window in &mut self.windows {
+                    window["is_focused"] = json!(window["id"] == args["id"]);
+                }
+            }
+        }
+    }
+
+    impl FakeNiri {
+        fn actions(&self) -> Vec<Value> {
+            self.state.lock().unwrap().actions.clone()
+        }
+
+        fn clear_actions(&self) {
+            self.state.lock().unwrap().actions.clear();
+        }
+
+        /// Workspace the window is on
+        fn workspace_of(&self, id: u64) -> Option<u64> {
+            let state = self.state.lock().unwrap();
+            let window = state.windows.iter().find(|w| w["id"] == id)?;
+            window["workspace_id"].as_u64()
+        }
+
+        fn set_windows(&self, windows: Vec<Value>) {
+            self.state.lock().unwrap().windows = windows;
+            self.publish();
+        }
+
+        fn set_workspaces(&self, workspaces: Vec<Value>) {
+            self.state.lock().unwrap().workspaces = workspaces;
+            self.publish();
+        }
+
+        /// Make every query fail until the next change
+        fn fail_queries(&self) {
+            for query in ["windows", "workspaces", "focused-window"] {
+                let _ = std::fs::remove_file(self.dir.join(format!("{query}.json")));
+            }
+        }
+
+        fn publish(&self) {
+            publish(&self.dir, &self.state.lock().unwrap());
+        }
+    }
+
+    fn publish(dir: &std::path::Path, state: &FakeState) {
+        let focused = state.windows.iter().find(|w| w["is_focused"] == true);
+        for (query, value) in [
+            ("windows", json!(state.windows)),
+            ("workspaces", json!(state.workspaces)),
+            ("focused-window", json!(focused)),
+        ] {
+            std::fs::write(dir.join(format!("{query}.json")), value.to_string()).unwrap();
+        }
+    }
+
+    /// Directory holding the fake `niri` script, written once per test run
+    fn fake_niri_bin() -> &'static std::path::Path {
+        static BIN: std::sync::OnceLock<std::path::PathBuf> = std::sync::OnceLock::new();
+        BIN.get_or_init(|| {
+            use std::os::unix::fs::PermissionsExt;
+            let dir = std::env::temp_dir().join(format!("nsticky-niri-{}-bin", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let script = dir.join("niri");
+            std::fs::write(
+                &script,
+                "#!/bin/sh\nexec cat \"$(dirname \"$NIRI_SOCKET\")/$3.json\"\n",
+            )
+            .unwrap();
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+            let path = std::env::var_os("PATH").unwrap_or_default();
+            let path = std::env::join_paths(
+                std::iter::once(dir.clone()).chain(std::env::split_paths(&path)),
+            )
+            .unwrap();
+            // SAFETY: only tests holding `FAKE_NIRI` read or write the variable
+            unsafe { std::env::set_var("PATH", path) };
+            dir
+        })
+    }
+
+    /// Start a fake niri refusing the actions `refuse` picks
+    async fn fake_niri(name: &str, refuse: fn(&Value) -> bool) -> FakeNiri {
+        fake_niri_bin();
+        let dir = std::env::temp_dir().join(format!("nsticky-niri-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("socket");
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        // SAFETY: only tests holding `FAKE_NIRI` read or write the variable
+        unsafe { std::env::set_var("NIRI_SOCKET", &socket) };
+
+        let state = Arc::new(std::sync::Mutex::new(FakeState::default()));
+        publish(&dir, &state.lock().unwrap());
+        let (served, files) = (state.clone(), dir.clone());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (reader, mut writer) = stream.into_split();
+                let mut line = String::new();
+                BufReader::new(reader).read_line(&mut line).await.unwrap();
+                let action = serde_json::from_str::<Value>(&line).unwrap()["Action"].take();
+                let reply = {
+                    let mut state = served.lock().unwrap();
+                    let reply = if refuse(&action) {
+                        "{\"Err\":\"refused\"}\n"
+                    } else {
+                        state.apply(&action);
+                        publish(&files, &state);
+                        "{\"Ok\":\"Handled\"}\n"
+                    };
+                    state.actions.push(action);
+                    reply
+                };
+                writer.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        FakeNiri { dir, state }
+    }
+
+    fn window(id: u64, app_id: &str, workspace_id: u64) -> Value {
+        json!({
+            "id": id,
+            "app_id": app_id,
+            "title": format!("{app_id} {id}"),
+            "pid": 1000 + id,
+            "workspace_id": workspace_id,
+            "is_focused": false,
+            "is_floating": false,
+        })
+    }
+
+    fn workspace(id: u64, name: Option<&str>, is_active: bool) -> Value {
+        json!({ "id": id, "idx": id, "name": name, "output": "DP-1", "is_active": is_active })
+    }
+
+    fn logic(config: &Config) -> BusinessLogic {
+        BusinessLogic::new(
+            Arc::default(),
+            Arc::default(),
+            Arc::default(),
+            Vec::new(),
+            config,
+        )
+    }
+
+    fn moved(id: u64, reference: Value) -> Value {
+        json!({ "MoveWindowToWorkspace": { "window_id": id, "focus": false, "reference": reference } })
+    }
+
+    fn focused(id: u64) -> Value {
+        json!({ "FocusWindow": { "id": id } })
+    }
+
+    #[test]
+    fn window_set_keeps_join_order_across_save_and_restore() {
+        let mut set = WindowSet::default();
+        for id in [11, 10, 12] {
+            set.insert(id);
+        }
+        assert!(!set.insert(10));
+        set.remove(&12);
+        set.insert(12);
+
+        let saved = set.save();
+        assert!(saved.windows(2).all(|w| w[0].since <= w[1].since));
+        let restored = WindowSet::restore(&saved, &HashSet::from([10, 11, 12]));
+        assert_eq!(restored.iter().copied().collect::<Vec<_>>(), [11, 10, 12]);
+        assert_eq!(restored.iter().next_back(), Some(&12));
+
+        let restored = WindowSet::restore(&saved, &HashSet::from([11, 12]));
+        assert_eq!(restored.iter().copied().collect::<Vec<_>>(), [11, 12]);
+    }
+
+    #[test]
+    fn layout_plan_follows_the_origin() {
+        let origin = |was_floating, floating, column| WindowOrigin {
+            workspace_id: 1,
+            workspace_idx: 1,
+            workspace_name: None,
+            output: None,
+            column,
+            was_floating,
+            floating,
+        };
+        let geometry = FloatingGeometry {
+            x: 120.5,
+            y: 80.0,
+            width: 640,
+            height: 480,
+        };
+
+        // Floating with unknown geometry stays floating and is not placed
+        let plan = plan_layout(&origin(true, None, None), true, true);
+        assert_eq!(plan, LayoutPlan::default());
+        let plan = plan_layout(&origin(true, None, None), false, true);
+        assert_eq!(plan.set_floating, Some(true));
+        assert_eq!(plan.place, None);
+
+        let plan = plan_layout(&origin(true, Some(geometry), None), true, false);
+        assert_eq!(plan.set_floating, None);
+        assert_eq!(plan.place, Some(geometry));
+
+        // Tiled windows are tiled again, and their column restored only on screen
+        let plan = plan_layout(&origin(false, None, Some(2)), true, true);
+        assert_eq!(plan.set_floating, Some(false));
+        assert_eq!(plan.column, Some(2));
+        let plan = plan_layout(&origin(false, None, Some(2)), false, false);
+        assert_eq!(plan, LayoutPlan::default());
+    }
+
+    /// One press of `stage cycle` against a simulated stage, most recently staged first
+    fn press(cycle: &mut StageCycle, staged: &mut Vec<u64>, reverse: bool) -> Option<u64> {
+        let step = cycle.advance(staged.clone(), |_| true, reverse)?;
+        if cycle.shown == Some(step.show) {
+            return Some(step.show);
+        }
+        if let Some(back) = step.send_back {
+            staged.insert(0, back);
+        }
+        staged.retain(|id| *id != step.show);
+        cycle.shown = Some(step.show);
+        Some(step.show)
+    }
+
+    #[test]
+    fn cycle_steps_through_the_stage_and_wraps_around() {
+        let mut cycle = StageCycle::default();
+        let mut staged = vec![3, 2, 1];
+        let shown: Vec<_> = (0..4)
+            .filter_map(|_| press(&mut cycle, &mut staged, false))
+            .collect();
+        assert_eq!(shown, [3, 2, 1, 3]);
+        assert_eq!(staged, [1, 2]);
+
+        assert_eq!(press(&mut cycle, &mut staged, true), Some(1));
+        assert_eq!(press(&mut cycle, &mut staged, true), Some(2));
+        assert_eq!(staged, [1, 3]);
+    }
+
+    #[test]
+    fn cycle_in_reverse_starts_from_the_oldest() {
+        let mut cycle = StageCycle::default();
+        let mut staged = vec![3, 2, 1];
+        assert_eq!(press(&mut cycle, &mut staged, true), Some(1));
+        assert_eq!(press(&mut cycle, &mut staged, true), Some(2));
+        assert_eq!(press(&mut cycle, &mut staged, true), Some(3));
+        assert_eq!(press(&mut cycle, &mut staged, true), Some(1));
+    }
+
+    #[test]
+    fn cycle_with_one_window_keeps_it_out() {
+        let mut cycle = StageCycle::default();
+        let mut staged = vec![7];
+        assert_eq!(press(&mut cycle, &mut staged, false), Some(7));
+        assert_eq!(press(&mut cycle, &mut staged, false), Some(7));
+        assert!(staged.is_empty());
+        assert_eq!(press(&mut cycle, &mut Vec::new(), false), Some(7));
+
+        let mut cycle = StageCycle::default();
+        assert_eq!(press(&mut cycle, &mut Vec::new(), false), None);
+    }
+
+    #[test]
+    fn cycle_starts_over_when_the_stage_changes() {
+        let mut cycle = StageCycle::default();
+        let mut staged = vec![3, 2, 1];
+        assert_eq!(press(&mut cycle, &mut staged, false), Some(3));
+        assert_eq!(press(&mut cycle, &mut staged, false), Some(2));
+
+        // Staging 4 outside the cycle restarts it; 2 stays out
+        staged.insert(0, 4);
+        assert_eq!(press(&mut cycle, &mut staged, false), Some(4));
+        assert_eq!(staged, [3, 1]);
+        assert_eq!(press(&mut cycle, &mut staged, false), Some(3));
+        assert_eq!(staged, [4, 1]);
+
+        // So does the shown window closing
+        let step = cycle.advance(staged.clone(), |id| id != 3, false).unwrap();
+        assert_eq!(
+            step,
+            CycleStep {
+                show: 4,
+                send_back: None
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn swap_brings_the_staged_window_in_and_stages_the_focused_one() {
+        let _niri = FAKE_NIRI.lock().await;
+        let niri = fake_niri("swap", |_| false).await;
+
+        exchange_windows(12, 10, 1, "stage").await.unwrap();
+        assert_eq!(
+            niri.actions(),
+            [
+                moved(12, json!({ "Id": 1 })),
+                focused(12),
+                moved(10, json!({ "Name": "stage" })),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn swap_rolls_back_when_staging_fails() {
+        let _niri = FAKE_NIRI.lock().await;
+        let niri = fake_niri("swap-rollback", |action| {
+            action["MoveWindowToWorkspace"]["window_id"] == 10
+        })
+        .await;
+
+        let err = exchange_windows(12, 10, 1, "stage").await.unwrap_err();
+        assert!(err.to_string().contains("refused"), "{err:#}");
+        assert_eq!(
+            niri.actions(),
+            [
+                moved(12, json!({ "Id": 1 })),
+                focused(12),
+                moved(10, json!({ "Name": "stage" })),
+                moved(12, json!({ "Name": "stage" })),
+                focused(10),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn workspace_activation_keeps_sticky_windows_when_niri_cannot_be_queried() {
+        let _niri = FAKE_NIRI.lock().await;
+        let niri = fake_niri("activation-query-fails", |_| false).await;
+        niri.set_workspaces(vec![workspace(1, None, false), workspace(2, None, true)]);
+        let logic = logic(&Config::default());
+        logic.sticky_windows.lock().await.insert(10);
+
+        niri.fail_queries();
+        assert!(logic.handle_workspace_activation(2).await.is_err());
+        assert!(logic.sticky_windows.lock().await.contains(&10));
+        assert!(niri.actions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn workspace_activation_forgets_sticky_windows_that_closed() {
+        let _niri = FAKE_NIRI.lock().await;
+        let niri = fake_niri("activation-closed", |_| false).await;
+        niri.set_workspaces(vec![workspace(1, None, false), workspace(2, None, true)]);
+        niri.set_windows(vec![window(10, "foot", 1)]);
+        let logic = logic(&Config::default());
+        let only = FollowScope::parse(&["1".to_string()], &[]).unwrap();
+        let origin = WindowOrigin {
+            workspace_id: 1,
+            workspace_idx: 1,
+            workspace_name: None,
+            output: None,
+            column: None,
+            was_floating: false,
+            floating: None,
+        };
+        for id in [10, 11] {
+            logic.sticky_windows.lock().await.insert(id);
+            logic.follow_scopes.lock().await.insert(id, only.clone());
+            logic.origins.lock().await.insert(id, origin.clone());
+        }
+
+        logic.handle_workspace_activation(2).await.unwrap();
+        assert_eq!(
+            logic
+                .sticky_windows
+                .lock()
+                .await
+                .iter()
+                .copied()
+                .collect::<Vec<_>>(),
+            [10]
+        );
+        let scopes = logic.follow_scopes.lock().await;
+        assert_eq!(scopes.keys().copied().collect::<Vec<_>>(), [10]);
+        let origins = logic.origins.lock().await;
+        assert_eq!(origins.keys().copied().collect::<Vec<_>>(), [10]);
+    }
+
+    #[tokio::test]
+    async fn minimized_windows_come_back_sticky_only_if_they_were() {
+        let _niri = FAKE_NIRI.lock().await;
+        let niri = fake_niri("minimize-restore", |_| false).await;
+        niri.set_workspaces(vec![
+            workspace(1, None, true),
+            workspace(2, Some("stage"), false),
+        ]);
+        niri.set_windows(vec![window(10, "foot", 1), window(11, "firefox", 1)]);
+        let logic = logic(&Config::default());
+        logic.sticky_windows.lock().await.insert(10);
+
+        logic.minimize_window(10).await.unwrap();
+        logic.minimize_window(11).await.unwrap();
+        assert_eq!(niri.workspace_of(10), Some(2));
+        assert_eq!(niri.workspace_of(11), Some(2));
+        assert!(logic.sticky_windows.lock().await.is_empty());
+        assert_eq!(*logic.staged_normal.lock().await, HashSet::from([11]));
+
+        let destination = logic.destination(1, Some(true));
+        assert!(logic.unstage_window(10, destination).await.unwrap());
+        assert!(!logic.unstage_window(11, destination).await.unwrap());
+        assert_eq!(niri.workspace_of(10), Some(1));
+        assert_eq!(niri.workspace_of(11), Some(1));
+        let sticky = logic.sticky_windows.lock().await;
+        assert_eq!(sticky.iter().copied().collect::<Vec<_>>(), [10]);
+        assert!(logic.staged_set.lock().await.is_empty());
+        assert!(logic.staged_normal.lock().await.is_empty());
+    }
+
+    /// Windows 10 and 12 staged, 11 focused on workspace 1 of 3
+    async fn staged_pair(name: &str) -> (FakeNiri, BusinessLogic) {
+        let niri = fake_niri(name, |_| false).await;
+        niri.set_workspaces(vec![
+            workspace(1, None, true),
+            workspace(2, Some("stage"), false),
+            workspace(3, None, false),
+        ]);
+        let mut focused = window(11, "foot", 1);
+        focused["is_focused"] = json!(true);
+        niri.set_windows(vec![window(10, "mpv", 2), focused, window(12, "mpv", 2)]);
+        let logic = logic(&Config::default());
+        for id in [10, 12] {
+            logic.staged_set.lock().await.insert(id);
+        }
+        (niri, logic)
+    }
+
+    async fn peek(logic: &BusinessLogic, id: u64, timeout: Option<Duration>) {
+        let selector = Selector::Id(id);
+        let peeked = logic.peek_matching(&selector, MatchStrategy::default(), 1, timeout);
+        assert_eq!(peeked.await.unwrap(), id);
+    }
+
+    #[tokio::test]
+    async fn peek_ends_when_the_window_loses_focus() {
+        let _niri = FAKE_NIRI.lock().await;
+        let (niri, logic) = staged_pair("peek-focus").await;
+
+        peek(&logic, 10, None).await;
+        assert_eq!(niri.workspace_of(10), Some(1));
+        // Focus events from before niri focused the window don't count
+        logic.handle_focus_change(Some(11)).await;
+        assert_eq!(niri.workspace_of(10), Some(1));
+        logic.handle_focus_change(Some(10)).await;
+        logic.handle_focus_change(Some(11)).await;
+        assert_eq!(niri.workspace_of(10), Some(2));
+        assert!(logic.peek.lock().await.is_none());
+        assert!(logic.staged_set.lock().await.contains(&10));
+    }
+
+    #[tokio::test]
+    async fn peek_ends_when_the_workspace_changes() {
+        let _niri = FAKE_NIRI.lock().await;
+        let (niri, logic) = staged_pair("peek-workspace").await;
+
+        peek(&logic, 10, None).await;
+        logic.handle_workspace_activation(1).await.unwrap();
+        assert_eq!(niri.workspace_of(10), Some(1));
+        logic.handle_workspace_activation(3).await.unwrap();
+        assert_eq!(niri.workspace_of(10), Some(2));
+        assert!(logic.peek.lock().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn peek_ends_when_it_times_out() {
+        let _niri = FAKE_NIRI.lock().await;
+        let (niri, logic) = staged_pair("peek-timeout").await;
+
+        peek(&logic, 10, Some(Duration::from_millis(50))).await;
+        assert_eq!(niri.workspace_of(10), Some(1));
+        for _ in 0..200 {
+            if niri.workspace_of(10) == Some(2) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(niri.workspace_of(10), Some(2));
+        assert!(logic.peek.lock().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn second_peek_sends_the_first_window_back() {
+        let _niri = FAKE_NIRI.lock().await;
+        let (niri, logic) = staged_pair("peek-twice").await;
+
+        peek(&logic, 10, None).await;
+        peek(&logic, 12, None).await;
+        assert_eq!(niri.workspace_of(10), Some(2));
+        assert_eq!(niri.workspace_of(12), Some(1));
+        assert_eq!(logic.peek.lock().await.map(|peek| peek.id), Some(12));
+    }
+
+    #[tokio::test]
+    async fn toggling_sticky_on_a_peeked_window_ends_the_peek() {
+        let _niri = FAKE_NIRI.lock().await;
+        let (niri, logic) = staged_pair("peek-sticky").await;
+        logic.staged_normal.lock().await.insert(10);
+        let mut events = logic.subscribe();
+
+        peek(&logic, 10, None).await;
+        assert!(logic.toggle_sticky_window(10).await.unwrap());
+        assert!(logic.peek.lock().await.is_none());
+        assert!(logic.sticky_windows.lock().await.contains(&10));
+        assert!(!logic.staged_set.lock().await.contains(&10));
+        assert!(logic.staged_normal.lock().await.is_empty());
+        assert_eq!(niri.workspace_of(10), Some(1));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(Event::Unstaged {
+                id: 10,
+                sticky: true
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn pop_returns_the_most_recently_staged_window_first() {
+        let _niri = FAKE_NIRI.lock().await;
+        let niri = fake_niri("pop-order", |_| false).await;
+        niri.set_workspaces(vec![
+            workspace(1, None, true),
+            workspace(2, Some("stage"), false),
+        ]);
+        niri.set_windows(vec![
+            window(10, "a", 1),
+            window(11, "b", 1),
+            window(12, "c", 1),
+        ]);
+        let logic = logic(&Config::default());
+        for id in [10, 11, 12] {
+            logic.sticky_windows.lock().await.insert(id);
+            logic.stage_window(id).await.unwrap();
+        }
+
+        let destination = Destination::Workspace(1);
+        let popped = logic.pop_staged(false, destination).await.unwrap();
+        assert_eq!(popped, Some((12, true)));
+        let popped = logic.pop_staged(false, destination).await.unwrap();
+        assert_eq!(popped, Some((11, true)));
+        assert_eq!(niri.workspace_of(10), Some(2));
+    }
+
+    /// Sticky windows 10 to 12 on workspace 1, with "games" and "dev" beside it
+    async fn sticky_trio(name: &str, config: &Config) -> (FakeNiri, BusinessLogic) {
+        let niri = fake_niri(name, |_| false).await;
+        niri.set_workspaces(vec![
+            workspace(1, Some("main"), true),
+            workspace(2, Some("games"), false),
+            workspace(3, Some("dev"), false),
+            workspace(4, Some("stage"), false),
+        ]);
+        niri.set_windows(vec![
+            window(10, "foot", 1),
+            window(11, "firefox", 1),
+            window(12, "mpv", 1),
+        ]);
+        let logic = logic(config);
+        for id in [10, 11, 12] {
+            logic.sticky_windows.lock().await.insert(id);
+        }
+        (niri, logic)
+    }
+
+    #[tokio::test]
+    async fn follow_scopes_decide_which_sticky_windows_follow() {
+        let _niri = FAKE_NIRI.lock().await;
+        let (niri, logic) = sticky_trio("follow-scopes", &Config::default()).await;
+        let never_games = FollowScope::parse(&[], &["games".to_string()]).unwrap();
+        let only_dev = FollowScope::parse(&["dev*".to_string()], &[]).unwrap();
+        logic
+            .set_follow_scope(&[10], Some(never_games))
+            .await
+            .unwrap();
+        logic.set_follow_scope(&[11], Some(only_dev)).await.unwrap();
+
+        logic.handle_workspace_activation(2).await.unwrap();
+        assert_eq!(niri.actions(), [moved(12, json!({ "Id": 2 }))]);
+
+        niri.clear_actions();
+        logic.handle_workspace_activation(3).await.unwrap();
+        assert_eq!(
+            niri.actions(),
+            [
+                moved(10, json!({ "Id": 3 })),
+                moved(11, json!({ "Id": 3 })),
+                moved(12, json!({ "Id": 3 })),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn sticky_windows_stay_off_reserved_workspaces_and_the_stage() {
+        let _niri = FAKE_NIRI.lock().await;
+        let config = Config {
+            reserved_workspaces: vec![WorkspacePattern::parse("gam*").unwrap()],
+            ..Config::default()
+        };
+        let (niri, logic) = sticky_trio("reserved-workspaces", &config).await;
+
+        logic.handle_workspace_activation(2).await.unwrap();
+        logic.handle_workspace_activation(4).await.unwrap();
+        assert!(niri.actions().is_empty());
+        for id in [10, 11, 12] {
+            assert_eq!(niri.workspace_of(id), Some(1));
+        }
+
+        logic.handle_workspace_activation(3).await.unwrap();
+        assert_eq!(niri.actions().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn refused_moves_leave_stage_and_sticky_lists_alone() {
+        let _niri = FAKE_NIRI.lock().await;
+        let niri = fake_niri("refused-moves", |_| true).await;
+        niri.set_workspaces(vec![
+            workspace(1, None, true),
+            workspace(2, Some("stage"), false),
+        ]);
+        niri.set_windows(vec![window(10, "mpv", 2), window(11, "foot", 1)]);
+        let logic = logic(&Config::default());
+        logic.staged_set.lock().await.insert(10);
+        logic.sticky_windows.lock().await.insert(11);
+
+        assert!(logic.stage_window(11).await.is_err());
+        let destination = Destination::Workspace(1);
+        assert!(logic.unstage_window(10, destination).await.is_err());
+        let sticky = logic.sticky_windows.lock().await;
+        assert_eq!(sticky.iter().copied().collect::<Vec<_>>(), [11]);
+        let staged = logic.staged_set.lock().await;
+        assert_eq!(staged.iter().copied().collect::<Vec<_>>(), [10]);
+    }
+}
@@ -1,21 +1,46 @@
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::UnixStream,
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{UnixStream, unix::OwnedReadHalf},
+};
+
+use crate::{
+    config,
+    daemon::{DaemonAction, DaemonArgs},
+    instance,
+    output::{self, OutputFormat},
+    protocol::{
+        self, ErrorCode, Event, JsonRequest, JsonResponse, MatchStrategy, PROTOCOL_VERSION,
+        Request, Response, ResponseData, ScopeArgs, StageArgs, Target, UnstageArgs, WindowEntry,
+    },
 };
 
 /// nsticky CLI client
 #[derive(Parser, Debug)]
 #[command(name = "nsticky")]
 #[command(about = "Manage sticky windows via CLI", long_about = None)]
-struct Cli {
+pub struct Cli {
+    /// Config file [default: $XDG_CONFIG_HOME/nsticky/config.toml]
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Control socket path [default: $NSTICKY_SOCKET or $XDG_RUNTIME_DIR/nsticky-<niri instance>.sock]
+    #[arg(long, global = true, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+    /// Without a command, nsticky runs the daemon in the foreground
     #[command(subcommand)]
-    command: Commands,
+    pub command: Option<Commands>,
 }
 
 #[derive(Subcommand, Debug)]
-enum Commands {
+pub enum Commands {
+    /// Run the nsticky daemon
+    #[command(args_conflicts_with_subcommands = true)]
+    Daemon(DaemonArgs),
     /// Manage sticky windows
     Sticky {
         #[command(subcommand)]
@@ -26,118 +51,865 @@ enum Commands {
         #[command(subcommand)]
         action: StageAction,
     },
+    /// Send any window to the stage, like minimizing it
+    Minimize {
+        /// Window ID or selector to minimize [default: the focused window]
+        target: Option<String>,
+        #[command(flatten)]
+        matching: MatchArgs,
+    },
+    /// Bring a staged window back, sticky only if it was sticky before staging
+    Restore {
+        /// Window ID or selector to restore [default: the most recently staged]
+        target: Option<String>,
+        #[command(flatten)]
+        matching: MatchArgs,
+        /// Return to the current workspace instead of where the window was staged from
+        #[arg(long)]
+        here: bool,
+    },
+    /// List every niri window with its sticky/staged state
+    #[command(alias = "w")]
+    Windows {
+        /// Only list windows matching this selector (e.g. app:firefox, floating)
+        selector: Option<String>,
+        #[command(flatten)]
+        format: ListFormat,
+    },
+    /// Show the daemon's window focus history, most recent first
+    FocusHistory,
+    /// Print sticky/staged state changes as JSON lines, starting with a snapshot
+    Watch,
+    /// Show sticky and staged window counts for status bars
+    Status {
+        /// Print Waybar custom-module JSON (text, tooltip, class, alt)
+        #[arg(long)]
+        waybar: bool,
+        /// Keep running and print again on every change
+        #[arg(long)]
+        follow: bool,
+        /// Text with {sticky}, {staged} and {total} placeholders [default: status_format from config or "{sticky} / {staged}"]
+        #[arg(long, value_name = "TEMPLATE")]
+        format: Option<String>,
+    },
 }
 
 #[derive(Subcommand, Debug)]
-enum StickyAction {
+pub enum StickyAction {
     /// Add window to sticky list
     #[command(alias = "a")]
     Add {
-        /// Window ID to add to sticky list
-        window_id: u64,
+        /// Window ID or selector (e.g. 42, app:firefox, "title~/Mail$/ and floating")
+        target: String,
+        #[command(flatten)]
+        matching: MatchArgs,
     },
     /// Remove window from sticky list
     #[command(alias = "r")]
     Remove {
-        /// Window ID to remove from sticky list
-        window_id: u64,
+        /// Window ID or selector to remove from sticky list
+        target: String,
+        #[command(flatten)]
+        matching: MatchArgs,
     },
     /// List all sticky windows
     #[command(alias = "l")]
-    List,
+    List {
+        #[command(flatten)]
+        format: ListFormat,
+    },
     /// Toggle active window in sticky list
     #[command(alias = "t")]
     ToggleActive,
+    /// Toggle window by ID or selector in sticky list
+    Toggle {
+        /// Window ID or selector to toggle
+        target: String,
+        #[command(flatten)]
+        matching: MatchArgs,
+    },
     /// Toggle window by app ID in sticky list
     #[command(alias = "ta")]
     ToggleAppid {
         /// Application ID to toggle
         appid: String,
+        #[command(flatten)]
+        matching: MatchArgs,
     },
     /// Toggle window by title in sticky list
     #[command(alias = "tt")]
     ToggleTitle {
         /// Window title to toggle
         title: String,
+        #[command(flatten)]
+        matching: MatchArgs,
+    },
+    /// Set which workspaces a sticky window follows onto, or show it without options
+    Scope {
+        /// Window ID or selector
+        target: String,
+        /// Follow only onto these workspaces: indexes, ranges like 1-4 or name globs like dev*
+        #[arg(long, value_name = "WORKSPACES", value_delimiter = ',')]
+        only: Vec<String>,
+        /// Never follow onto these workspaces
+        #[arg(long, value_name = "WORKSPACES", value_delimiter = ',')]
+        never: Vec<String>,
+        /// Drop the window's own scope, falling back to follow_rule entries in the config
+        #[arg(long, conflicts_with_all = ["only", "never"])]
+        clear: bool,
+        #[command(flatten)]
+        matching: MatchArgs,
     },
 }
 
 #[derive(Subcommand, Debug)]
-enum StageAction {
-    /// List all staged windows
+pub enum StageAction {
+    /// List all staged windows, most recently staged first
     #[command(alias = "l")]
-    List,
+    List {
+        #[command(flatten)]
+        format: ListFormat,
+    },
     /// Add window to stage (move from sticky to stage workspace)
     #[command(alias = "a")]
     Add {
-        /// Window ID to stage
-        window_id: u64,
+        /// Window ID or selector to stage
+        target: String,
+        #[command(flatten)]
+        matching: MatchArgs,
     },
     /// Remove window from stage (move from stage to current workspace)
     #[command(alias = "r")]
     Remove {
-        /// Window ID to unstage
-        window_id: u64,
+        /// Window ID or selector to unstage
+        target: String,
+        #[command(flatten)]
+        matching: MatchArgs,
+        #[command(flatten)]
+        placement: PlacementArgs,
+    },
+    /// Remove window with app ID from stage (move back to current workspace)
+    #[command(alias = "rap")]
+    RemoveAppid {
+        /// Application ID to unstage
+        appid: String,
+        #[command(flatten)]
+        matching: MatchArgs,
+        #[command(flatten)]
+        placement: PlacementArgs,
+    },
+    /// Remove window with title from stage (move back to current workspace)
+    #[command(alias = "rt")]
+    RemoveTitle {
+        /// Window title to unstage
+        title: String,
+        #[command(flatten)]
+        matching: MatchArgs,
+        #[command(flatten)]
+        placement: PlacementArgs,
+    },
+    /// Bring back the most recently staged window
+    Pop {
+        /// Bring back the window staged longest ago instead
+        #[arg(long)]
+        oldest: bool,
+        #[command(flatten)]
+        placement: PlacementArgs,
+    },
+    /// Show the window `stage pop` would bring back, or show a staged window for a moment
+    Peek {
+        /// Staged window to show focused until it loses focus, the workspace changes or the timeout passes
+        target: Option<String>,
+        /// Show the window staged longest ago instead
+        #[arg(long, conflicts_with = "target")]
+        oldest: bool,
+        /// Seconds before the window goes back [default: peek_timeout from config, 10]
+        #[arg(
+            long,
+            value_name = "SECS",
+            requires = "target",
+            conflicts_with = "oldest"
+        )]
+        timeout: Option<u64>,
+        #[command(flatten)]
+        matching: MatchArgs,
+        #[command(flatten)]
+        format: ListFormat,
+    },
+    /// Stage the focused sticky window and bring back a staged one in its place
+    Swap {
+        /// Staged window ID or selector to bring back [default: the most recently staged]
+        target: Option<String>,
+        #[command(flatten)]
+        matching: MatchArgs,
+    },
+    /// Show the next staged window, sending the one shown before back to the stage
+    Cycle {
+        /// Go through the stage from the oldest window instead
+        #[arg(long)]
+        reverse: bool,
+    },
+    /// Remove active window from stage (move back to current workspace)
+    #[command(alias = "rac")]
+    RemoveActive {
+        #[command(flatten)]
+        placement: PlacementArgs,
     },
     /// Toggle active window stage status (stage/unstage; makes sticky if needed)
     #[command(alias = "t")]
     ToggleActive,
+    /// Toggle window by ID or selector in stage
+    Toggle {
+        /// Window ID or selector to toggle
+        target: String,
+        #[command(flatten)]
+        matching: MatchArgs,
+    },
     /// Toggle window by app ID in stage
     #[command(alias = "ta")]
     ToggleAppid {
         /// Application ID to toggle
         appid: String,
+        #[command(flatten)]
+        matching: MatchArgs,
     },
     /// Toggle window by title in stage
     #[command(alias = "tt")]
     ToggleTitle {
         /// Window title to toggle
         title: String,
+        #[command(flatten)]
+        matching: MatchArgs,
     },
     /// Add all sticky windows to stage
     #[command(alias = "aa")]
     AddAll,
     /// Remove all staged windows
     #[command(alias = "ra")]
-    RemoveAll,
+    RemoveAll {
+        #[command(flatten)]
+        placement: PlacementArgs,
+    },
 }
 
-pub async fn run_cli() -> Result<()> {
-    let cli = Cli::parse();
+/// Strategy for commands whose app ID, title or selector matches several windows
+#[derive(Args, Debug)]
+#[group(multiple = false)]
+pub struct MatchArgs {
+    /// Act on every matching window
+    #[arg(long)]
+    all: bool,
+    /// Act on the first matching window in niri's window list
+    #[arg(long)]
+    first: bool,
+    /// Act on the matching window that has focus
+    #[arg(long)]
+    focused: bool,
+    /// Act on the most recently focused matching window (default)
+    #[arg(long)]
+    mru: bool,
+}
 
-    let socket_path = "/tmp/niri_sticky_cli.sock";
-    let stream = UnixStream::connect(socket_path).await?;
-    let (reader, mut writer) = stream.into_split();
-    let mut reader = BufReader::new(reader);
+impl MatchArgs {
+    fn strategy(&self) -> MatchStrategy {
+        if self.all {
+            MatchStrategy::All
+        } else if self.focused {
+            MatchStrategy::Focused
+        } else if self.first {
+            MatchStrategy::First
+        } else {
+            MatchStrategy::Mru
+        }
+    }
+}
+
+/// Where unstaged windows go (the `unstage_to_origin` config by default)
+#[derive(Args, Debug)]
+#[group(multiple = false)]
+pub struct PlacementArgs {
+    /// Return to the workspace and position the window was staged from
+    #[arg(long)]
+    origin: bool,
+    /// Return to the current workspace
+    #[arg(long)]
+    here: bool,
+}
+
+impl PlacementArgs {
+    fn origin(&self) -> Option<bool> {
+        if self.origin {
+            Some(true)
+        } else if self.here {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+/// Output format of list commands (an aligned table by default)
+#[derive(Args, Debug)]
+#[group(multiple = false)]
+pub struct ListFormat {
+    /// Print windows as JSON
+    #[arg(long)]
+    json: bool,
+    /// Print only window IDs, one per line
+    #[arg(long)]
+    ids_only: bool,
+    /// Print one line per window from a template, e.g. '{id} {app_id}'
+    #[arg(long, value_name = "TEMPLATE")]
+    format: Option<String>,
+}
+
+impl ListFormat {
+    fn output_format(self) -> OutputFormat {
+        if self.json {
+            OutputFormat::Json
+        } else if self.ids_only {
+            OutputFormat::IdsOnly
+        } else if let Some(template) = self.format {
+            OutputFormat::Template(template)
+        } else {
+            OutputFormat::Table
+        }
+    }
+}
 
-    // Generate command string based on subcommand
-    let cmd_str = match cli.command {
+/// Process exit status of a CLI command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Success = 0,
+    /// Any failure without a more specific status, e.g. niri errors
+    Failure = 1,
+    /// Bad arguments or selector syntax (clap usage errors exit with 2 as well)
+    InvalidRequest = 2,
+    NotFound = 3,
+    /// The window is not in a state that allows the operation
+    InvalidState = 4,
+    /// The command succeeded but changed nothing
+    Unchanged = 5,
+    DaemonUnreachable = 6,
+}
+
+impl From<ErrorCode> for Status {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::InvalidRequest | ErrorCode::UnsupportedVersion => Status::InvalidRequest,
+            ErrorCode::NotFound => Status::NotFound,
+            ErrorCode::InvalidState => Status::InvalidState,
+            ErrorCode::Internal => Status::Failure,
+        }
+    }
+}
+
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
+
+/// Send a client command to the daemon and print the result
+/// `socket` overrides the socket from the environment and config
+pub async fn run_cli(
+    command: Commands,
+    config_path: Option<&Path>,
+    socket: Option<PathBuf>,
+) -> ExitCode {
+    let config = match config::load(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {e:#}");
+            return Status::Failure.into();
+        }
+    };
+    let socket_path = socket.unwrap_or_else(|| instance::socket_path(config.socket));
+    let stream = match UnixStream::connect(&socket_path).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!(
+                "Error: nsticky daemon is not reachable at {}: {e}",
+                socket_path.display()
+            );
+            return Status::DaemonUnreachable.into();
+        }
+    };
+
+    let result = match command {
+        Commands::Watch => watch(stream).await,
+        Commands::Status {
+            waybar,
+            follow,
+            format,
+        } => {
+            let format = format
+                .or(config.status_format)
+                .unwrap_or_else(|| output::DEFAULT_STATUS_FORMAT.to_string());
+            status(stream, &socket_path, waybar, follow, &format).await
+        }
+        command => {
+            let (request, format) = build_request(command);
+            let stopping = matches!(request, Request::Shutdown { .. });
+            let result = match send_request(stream, request).await {
+                Ok(response) => print_response(response, format),
+                Err(e) => Err(e),
+            };
+            if stopping && matches!(result, Ok(Status::Success)) {
+                wait_for_exit(&socket_path).await.map(|()| Status::Success)
+            } else {
+                result
+            }
+        }
+    };
+    match result {
+        Ok(status) => status.into(),
+        Err(e) => {
+            eprintln!("Error: {e:#}");
+            Status::Failure.into()
+        }
+    }
+}
+
+/// Wait until the daemon on a socket has saved its state and released its lock
+async fn wait_for_exit(socket_path: &Path) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + instance::HANDOVER_TIMEOUT;
+    while instance::running_pid(socket_path)?.is_some() {
+        if tokio::time::Instant::now() >= deadline {
+            anyhow::bail!(
+                "Daemon did not exit within {:?}",
+                instance::HANDOVER_TIMEOUT
+            );
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    Ok(())
+}
+
+/// Translate a subcommand into its daemon request, plus the output format for window lists
+fn build_request(command: Commands) -> (Request, Option<OutputFormat>) {
+    let request = match command {
         Commands::Sticky { action } => match action {
-            StickyAction::Add { window_id } => format!("add {window_id}\n"),
-            StickyAction::Remove { window_id } => format!("remove {window_id}\n"),
-            StickyAction::List => "list\n".to_string(),
-            StickyAction::ToggleActive => "toggle_active\n".to_string(),
-            StickyAction::ToggleAppid { appid } => format!("toggle_appid {appid}\n"),
-            StickyAction::ToggleTitle { title } => format!("toggle_title \"{title}\"\n"),
+            StickyAction::Add { target, matching } => {
+                Request::Add(Target::from_arg(target, matching.strategy()))
+            }
+            StickyAction::Remove { target, matching } => {
+                Request::Remove(Target::from_arg(target, matching.strategy()))
+            }
+            StickyAction::Toggle { target, matching } => {
+                Request::Toggle(Target::from_arg(target, matching.strategy()))
+            }
+            StickyAction::List { format } => return (Request::List, Some(format.output_format())),
+            StickyAction::ToggleActive => Request::ToggleActive,
+            StickyAction::ToggleAppid { appid, matching } => Request::ToggleAppid {
+                appid,
+                strategy: matching.strategy(),
+            },
+            StickyAction::ToggleTitle { title, matching } => Request::ToggleTitle {
+                title,
+                strategy: matching.strategy(),
+            },
+            StickyAction::Scope {
+                target,
+                only,
+                never,
+                clear,
+                matching,
+            } => {
+                let target = Target::from_arg(target, matching.strategy());
+                Request::Scope(ScopeArgs {
+                    window_id: target.window_id,
+                    selector: target.selector,
+                    strategy: target.strategy,
+                    only,
+                    never,
+                    clear,
+                })
+            }
         },
         Commands::Stage { action } => match action {
-            StageAction::List => "stage --list\n".to_string(),
-            StageAction::Add { window_id } => format!("stage {window_id}\n"),
-            StageAction::Remove { window_id } => format!("unstage {window_id}\n"),
-            StageAction::ToggleActive => "stage --active\n".to_string(),
-            StageAction::ToggleAppid { appid } => format!("stage --toggle-appid {appid}\n"),
-            StageAction::ToggleTitle { title } => format!("stage --toggle-title \"{title}\"\n"),
-            StageAction::AddAll => "stage --all\n".to_string(),
-            StageAction::RemoveAll => "unstage --all\n".to_string(),
+            StageAction::List { format } => {
+                let request = Request::Stage(StageArgs {
+                    list: true,
+                    ..Default::default()
+                });
+                return (request, Some(format.output_format()));
+            }
+            StageAction::Add { target, matching } => {
+                let target = Target::from_arg(target, matching.strategy());
+                Request::Stage(StageArgs {
+                    window_id: target.window_id,
+                    selector: target.selector,
+                    strategy: target.strategy,
+                    ..Default::default()
+                })
+            }
+            StageAction::Remove {
+                target,
+                matching,
+                placement,
+            } => {
+                let target = Target::from_arg(target, matching.strategy());
+                Request::Unstage(UnstageArgs {
+                    window_id: target.window_id,
+                    selector: target.selector,
+                    strategy: target.strategy,
+                    origin: placement.origin(),
+                    ..Default::default()
+                })
+            }
+            StageAction::Toggle { target, matching } => {
+                let target = Target::from_arg(target, matching.strategy());
+                Request::Stage(StageArgs {
+                    window_id: target.window_id,
+                    selector: target.selector,
+                    strategy: target.strategy,
+                    toggle: true,
+                    ..Default::default()
+                })
+            }
+            StageAction::RemoveAppid {
+                appid,
+                matching,
+                placement,
+            } => Request::Unstage(UnstageArgs {
+                appid: Some(appid),
+                strategy: matching.strategy(),
+                origin: placement.origin(),
+                ..Default::default()
+            }),
+            StageAction::RemoveTitle {
+                title,
+                matching,
+                placement,
+            } => Request::Unstage(UnstageArgs {
+                title: Some(title),
+                strategy: matching.strategy(),
+                origin: placement.origin(),
+                ..Default::default()
+            }),
+            StageAction::RemoveActive { placement } => Request::Unstage(UnstageArgs {
+                active: true,
+                origin: placement.origin(),
+                ..Default::default()
+            }),
+            StageAction::ToggleActive => Request::Stage(StageArgs {
+                active: true,
+                ..Default::default()
+            }),
+            StageAction::ToggleAppid { appid, matching } => Request::Stage(StageArgs {
+                appid: Some(appid),
+                strategy: matching.strategy(),
+                ..Default::default()
+            }),
+            StageAction::ToggleTitle { title, matching } => Request::Stage(StageArgs {
+                title: Some(title),
+                strategy: matching.strategy(),
+                ..Default::default()
+            }),
+            StageAction::AddAll => Request::Stage(StageArgs {
+                all: true,
+                ..Default::default()
+            }),
+            StageAction::Pop { oldest, placement } => Request::Pop {
+                oldest,
+                origin: placement.origin(),
+            },
+            StageAction::Peek {
+                target: Some(target),
+                timeout,
+                matching,
+                ..
+            } => Request::PeekWindow {
+                target: Target::from_arg(target, matching.strategy()),
+                timeout_secs: timeout,
+            },
+            StageAction::Peek {
+                target: None,
+                oldest,
+                format,
+                ..
+            } => {
+                return (Request::Peek { oldest }, Some(format.output_format()));
+            }
+            StageAction::Swap { target, matching } => Request::Swap(match target {
+                Some(target) => Target::from_arg(target, matching.strategy()),
+                None => Target {
+                    strategy: matching.strategy(),
+                    ..Default::default()
+                },
+            }),
+            StageAction::Cycle { reverse } => Request::Cycle { reverse },
+            StageAction::RemoveAll { placement } => Request::Unstage(UnstageArgs {
+                all: true,
+                origin: placement.origin(),
+                ..Default::default()
+            }),
+        },
+        Commands::Minimize { target, matching } => Request::Minimize(match target {
+            Some(target) => Target::from_arg(target, matching.strategy()),
+            None => Target {
+                strategy: matching.strategy(),
+                ..Default::default()
+            },
+        }),
+        Commands::Restore {
+            target: None, here, ..
+        } => Request::Pop {
+            oldest: false,
+            origin: Some(!here),
         },
+        Commands::Restore {
+            target: Some(target),
+            matching,
+            here,
+        } => {
+            let target = Target::from_arg(target, matching.strategy());
+            Request::Unstage(UnstageArgs {
+                window_id: target.window_id,
+                selector: target.selector,
+                strategy: target.strategy,
+                origin: Some(!here),
+                ..Default::default()
+            })
+        }
+        Commands::Windows { selector, format } => {
+            return (Request::Windows { selector }, Some(format.output_format()));
+        }
+        Commands::FocusHistory => Request::FocusHistory,
+        Commands::Watch | Commands::Status { .. } => {
+            unreachable!("streaming commands are handled by run_cli")
+        }
+        Commands::Daemon(DaemonArgs {
+            action:
+                Some(DaemonAction::Stop {
+                    restore_staged,
+                    keep_staged,
+                }),
+            ..
+        }) => {
+            let restore_staged = match (restore_staged, keep_staged) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            };
+            Request::Shutdown { restore_staged }
+        }
+        Commands::Daemon(_) => unreachable!("running the daemon is not sent to a daemon"),
     };
+    (request, None)
+}
+
+/// Send one JSON request to the daemon and decode its response
+async fn send_request(stream: UnixStream, request: Request) -> Result<JsonResponse> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
 
-    writer.write_all(cmd_str.as_bytes()).await?;
+    let request = JsonRequest {
+        version: PROTOCOL_VERSION,
+        request,
+    };
+    let line = serde_json::to_string(&request)?;
+    writer.write_all(format!("{line}\n").as_bytes()).await?;
     writer.flush().await?;
 
     let mut response = String::new();
     reader.read_line(&mut response).await?;
-    print!("{response}");
+    serde_json::from_str(&response).context("Invalid response from daemon")
+}
 
-    Ok(())
+/// Subscription to the daemon's state changes
+struct EventStream {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+}
+
+impl EventStream {
+    async fn open(stream: UnixStream) -> Result<Self> {
+        let (reader, mut writer) = stream.into_split();
+        let request = serde_json::to_string(&JsonRequest {
+            version: PROTOCOL_VERSION,
+            request: Request::Watch,
+        })?;
+        writer.write_all(format!("{request}\n").as_bytes()).await?;
+        writer.flush().await?;
+        Ok(Self {
+            lines: BufReader::new(reader).lines(),
+        })
+    }
+
+    /// Next event, `None` once the daemon closes the stream
+    async fn next(&mut self) -> Result<Option<Event>> {
+        let line = match self.lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => return Ok(None),
+            // A daemon killed mid-stream resets the connection instead of closing it
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionReset => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if let Ok(event) = serde_json::from_str(&line) {
+            return Ok(Some(event));
+        }
+        // Anything else is a rejected request
+        let response: JsonResponse =
+            serde_json::from_str(&line).context("Invalid event from daemon")?;
+        match response.error {
+            Some(error) => Err(anyhow::anyhow!(error.message)),
+            None => anyhow::bail!("Invalid event from daemon"),
+        }
+    }
+}
+
+/// Print events as JSON lines until the daemon goes away or stdout is closed
+async fn watch(stream: UnixStream) -> Result<Status> {
+    let mut events = EventStream::open(stream).await?;
+    let mut stdout = std::io::stdout();
+    while let Some(event) = events.next().await? {
+        let line = serde_json::to_string(&event)?;
+        if writeln!(stdout, "{line}")
+            .and_then(|()| stdout.flush())
+            .is_err()
+        {
+            return Ok(Status::Success);
+        }
+    }
+    eprintln!("Error: nsticky daemon closed the event stream");
+    Ok(Status::DaemonUnreachable)
+}
+
+/// Print the status line once, or again on every change with `follow`
+async fn status(
+    stream: UnixStream,
+    socket_path: &Path,
+    waybar: bool,
+    follow: bool,
+    format: &str,
+) -> Result<Status> {
+    let render = |sticky: &[WindowEntry], staged: &[WindowEntry]| {
+        if waybar {
+            output::render_waybar(sticky, staged, format)
+        } else {
+            output::render_status_text(sticky, staged, format)
+        }
+    };
+    // Fail on a bad format before waiting for any change
+    render(&[], &[])?;
+
+    if !follow {
+        let (sticky, staged) = fetch_status(stream).await?;
+        println!("{}", render(&sticky, &staged)?);
+        return Ok(Status::Success);
+    }
+
+    let mut events = EventStream::open(stream).await?;
+    let mut stdout = std::io::stdout();
+    let mut last = None;
+    while let Some(event) = events.next().await? {
+        let (sticky, staged) = match event {
+            Event::Snapshot { sticky, staged } => (sticky, staged),
+            Event::Sticky { .. }
+            | Event::Unsticky { .. }
+            | Event::Staged { .. }
+            | Event::Unstaged { .. }
+            | Event::Closed { .. } => {
+                // Events carry only ids, so look up app IDs and titles again
+                fetch_status(UnixStream::connect(socket_path).await?).await?
+            }
+            Event::FollowCompleted { .. } | Event::Error { .. } => continue,
+        };
+        let line = render(&sticky, &staged)?;
+        if last.as_ref() == Some(&line) {
+            continue;
+        }
+        if writeln!(stdout, "{line}")
+            .and_then(|()| stdout.flush())
+            .is_err()
+        {
+            return Ok(Status::Success);
+        }
+        last = Some(line);
+    }
+    eprintln!("Error: nsticky daemon closed the event stream");
+    Ok(Status::DaemonUnreachable)
+}
+
+/// Sticky and staged windows from a `status` request
+async fn fetch_status(stream: UnixStream) -> Result<(Vec<WindowEntry>, Vec<WindowEntry>)> {
+    let response = send_request(stream, Request::Status).await?;
+    if let Some(error) = response.error {
+        anyhow::bail!(error.message);
+    }
+    let data = response.data.context("Status response without data")?;
+    match serde_json::from_value(data)? {
+        ResponseData::Status { sticky, staged } => Ok((sticky, staged)),
+        _ => anyhow::bail!("Unexpected status response from daemon"),
+    }
+}
+
+/// Print a response (errors to stderr) and work out the exit status
+fn print_response(response: JsonResponse, format: Option<OutputFormat>) -> Result<Status> {
+    if let Some(error) = response.error {
+        eprintln!("Error: {}", error.message);
+        return Ok(error.code.into());
+    }
+
+    if let Some(format) = format {
+        let windows: Vec<WindowEntry> = match response.data {
+            Some(mut data) => serde_json::from_value(data["windows"].take())?,
+            None => Vec::new(),
+        };
+        print!("{}", output::render_windows(&windows, &format)?);
+    } else if let Some(message) = response.message {
+        println!("{message}");
+    } else if let Some(data) = response.data {
+        let data: ResponseData = serde_json::from_value(data)?;
+        print!("{}", protocol::format_response(Response::Data(data)));
+    }
+
+    if response.changed == Some(false) {
+        Ok(Status::Unchanged)
+    } else {
+        Ok(Status::Success)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The CLI only speaks JSON, so titles reach the daemon through serde, not the text encoder
+    #[test]
+    fn titles_reach_the_daemon_unchanged() {
+        for title in [
+            "  two  spaces  ",
+            "\"Inbox\"\t—  Mail",
+            "C:\\path\\ends with \\",
+            "line\nbreak\r",
+            "",
+        ] {
+            let cli = Cli::try_parse_from(["nsticky", "sticky", "toggle-title", title]).unwrap();
+            let (request, _) = build_request(cli.command.unwrap());
+            let line = serde_json::to_string(&JsonRequest {
+                version: PROTOCOL_VERSION,
+                request,
+            })
+            .unwrap();
+            assert!(!line.contains('\n'), "request must stay on one line");
+            match protocol::parse_json_request(&line).unwrap() {
+                Request::ToggleTitle { title: parsed, .. } => assert_eq!(parsed, title),
+                other => panic!("unexpected request: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn restore_without_a_target_brings_back_the_stage_top() {
+        let cli = Cli::try_parse_from(["nsticky", "restore", "--here"]).unwrap();
+        match build_request(cli.command.unwrap()).0 {
+            Request::Pop {
+                oldest: false,
+                origin: Some(false),
+            } => {}
+            other => panic!("unexpected request: {other:?}"),
+        }
+    }
 }
@@ -0,0 +1,158 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::scope::{FollowScope, WorkspacePattern};
+use crate::selector::{self, Selector};
+
+/// Settings read from `config.toml`
+///
+/// Command-line flags take precedence over the file.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Control socket path
+    pub socket: Option<PathBuf>,
+    /// Log verbosity
+    pub log_level: Option<LogLevel>,
+    /// Per-module log levels, e.g. `nsticky::system_integration=debug`
+    pub log_filter: Option<String>,
+    /// Log line format
+    pub log_format: Option<LogFormat>,
+    /// Append log lines to this file instead of stderr
+    pub log_file: Option<PathBuf>,
+    /// Name of the workspace staged windows are moved to
+    pub stage_workspace: String,
+    /// Workspaces sticky windows never follow onto, besides the stage workspace
+    pub reserved_workspaces: Vec<WorkspacePattern>,
+    /// Bring staged windows back to the current workspace when the daemon stops
+    pub restore_staged_on_exit: bool,
+    /// Return unstaged windows to the workspace and position they were staged from
+    pub unstage_to_origin: bool,
+    /// Seconds a window shown by `stage peek` stays out; 0 waits for focus loss only
+    pub peek_timeout: u64,
+    /// Text of `nsticky status`, e.g. `"📌 {sticky} / 📦 {staged}"`
+    pub status_format: Option<String>,
+    /// Follow scopes for sticky windows without one of their own; first match wins
+    pub follow_rule: Vec<FollowRule>,
+}
+
+/// Follow scope for sticky windows matching a selector
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FollowRule {
+    /// Window selector, e.g. `app:firefox`
+    #[serde(rename = "match")]
+    pub selector: String,
+    #[serde(default)]
+    pub only: Vec<WorkspacePattern>,
+    #[serde(default)]
+    pub never: Vec<WorkspacePattern>,
+}
+
+impl Config {
+    /// Parsed follow rules, in config order
+    pub fn follow_rules(&self) -> Result<Vec<(Selector, FollowScope)>> {
+        self.follow_rule
+            .iter()
+            .map(|rule| {
+                let selector = selector::parse(&rule.selector)
+                    .with_context(|| format!("Invalid follow_rule match '{}'", rule.selector))?;
+                let scope = FollowScope {
+                    only: rule.only.clone(),
+                    never: rule.never.clone(),
+                };
+                Ok((selector, scope))
+            })
+            .collect()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            socket: None,
+            log_level: None,
+            log_filter: None,
+            log_format: None,
+            log_file: None,
+            stage_workspace: "stage".to_string(),
+            reserved_workspaces: Vec::new(),
+            restore_staged_on_exit: false,
+            unstage_to_origin: false,
+            peek_timeout: 10,
+            status_format: None,
+            follow_rule: Vec::new(),
+        }
+    }
+}
+
+/// Log verbosity of the daemon
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for tracing::level_filters::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => Self::OFF,
+            LogLevel::Error => Self::ERROR,
+            LogLevel::Warn => Self::WARN,
+            LogLevel::Info => Self::INFO,
+            LogLevel::Debug => Self::DEBUG,
+            LogLevel::Trace => Self::TRACE,
+        }
+    }
+}
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// `$XDG_CONFIG_HOME/nsticky/config.toml`, falling back to `~/.config`
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("nsticky").join("config.toml"))
+}
+
+/// Load the config file
+///
+/// An explicitly given file must exist; a missing default file yields the defaults.
+pub fn load(path: Option<&Path>) -> Result<Config> {
+    let (path, explicit) = match path {
+        Some(path) => (path.to_path_buf(), true),
+        None => match default_path() {
+            Some(path) => (path, false),
+            None => return Ok(Config::default()),
+        },
+    };
+
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if !explicit && e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Config::default());
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read config {}", path.display()));
+        }
+    };
+    toml::from_str(&text).with_context(|| format!("Invalid config {}", path.display()))
+}
@@ -1,56 +1,354 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde_json::Value;
-use std::collections::HashSet;
-use std::future;
+use std::collections::VecDeque;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     net::{UnixListener, UnixStream},
-    sync::Mutex,
+    signal::unix::{SignalKind, signal},
+    sync::{Mutex, RwLock, broadcast::error::RecvError, mpsc},
 };
 
-use crate::{business::BusinessLogic, protocol};
+use tracing::Instrument;
 
-pub async fn start(sticky_windows: Arc<Mutex<HashSet<u64>>>) -> Result<()> {
-    let staged_set = Arc::new(Mutex::new(HashSet::new()));
-    let business_logic = BusinessLogic::new(sticky_windows, staged_set);
+use crate::{
+    business::{BusinessLogic, WindowSet},
+    config::{self, Config, LogFormat, LogLevel},
+    instance,
+    logging::{self, LogSettings},
+    protocol,
+    scope::FollowScope,
+    selector::{self, Selector},
+    state, systemd,
+};
 
-    let cli_business_logic = business_logic.clone();
-    tokio::spawn(async move {
-        if let Err(_e) = run_cli_server(cli_business_logic).await {
-            eprintln!("CLI server error: {_e:?}");
+/// Options of `nsticky daemon`
+#[derive(clap::Args, Debug, Default)]
+pub struct DaemonArgs {
+    /// Config file, from the global `--config`
+    #[arg(skip)]
+    pub config: Option<PathBuf>,
+    /// Control socket path, from the global `--socket`
+    #[arg(skip)]
+    pub socket: Option<PathBuf>,
+    /// Log verbosity [default: info]
+    #[arg(long, value_enum, value_name = "LEVEL")]
+    pub log_level: Option<LogLevel>,
+    /// Per-module log levels, e.g. `nsticky::system_integration=debug`
+    #[arg(long, value_name = "DIRECTIVES")]
+    pub log_filter: Option<String>,
+    /// Log line format [default: text]
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub log_format: Option<LogFormat>,
+    /// Append log lines to a file instead of stderr [default when detached: the
+    /// socket path with a `.log` extension]
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+    /// Stay attached to the terminal instead of detaching into the background
+    #[arg(long)]
+    pub foreground: bool,
+    /// Take over the control socket from a daemon that is already running
+    #[arg(long)]
+    pub replace: bool,
+    #[command(subcommand)]
+    pub action: Option<DaemonAction>,
+}
+
+/// Commands sent to a running daemon
+#[derive(clap::Subcommand, Debug)]
+pub enum DaemonAction {
+    /// Stop the running daemon, saving its state
+    Stop {
+        /// Bring staged windows back to the current workspace first
+        #[arg(long, conflicts_with = "keep_staged")]
+        restore_staged: bool,
+        /// Leave staged windows on the stage workspace (overrides restore_staged_on_exit)
+        #[arg(long)]
+        keep_staged: bool,
+    },
+}
+
+/// Run the daemon, detaching into the background unless `--foreground` is given
+pub async fn run(args: DaemonArgs) -> Result<()> {
+    let config = config::load(args.config.as_deref())?;
+    let follow_rules = config.follow_rules()?;
+    let log_file = args.log_file.or(config.log_file.clone());
+    // Before taking over systemd's sockets, so warnings about them are logged
+    logging::init(LogSettings {
+        level: args.log_level.or(config.log_level).unwrap_or_default(),
+        filter: args.log_filter.or(config.log_filter.clone()),
+        format: args.log_format.or(config.log_format).unwrap_or_default(),
+        file: log_file.clone(),
+    })?;
+    let activated = systemd::activated_listener()?;
+    // A socket handed over by systemd decides where CLI clients connect
+    let socket_path = match activated.as_ref().map(|l| l.local_addr()).transpose()? {
+        Some(addr) => addr
+            .as_pathname()
+            .context("Activated socket has no path")?
+            .to_path_buf(),
+        None => args
+            .socket
+            .clone()
+            .unwrap_or_else(|| instance::socket_path(config.socket.clone())),
+    };
+
+    // systemd supervises the process it started, so never detach when activated
+    if !args.foreground && activated.is_none() {
+        if !args.replace
+            && let Some(pid) = instance::running_pid(&socket_path)?
+        {
+            anyhow::bail!(
+                "nsticky daemon already running (pid {pid}) on {}; use --replace to take over",
+                socket_path.display()
+            );
+        }
+        return spawn_background(&socket_path, log_file).await;
+    }
+
+    // Dropping the instance on shutdown removes our socket and releases the lock;
+    // an activated socket belongs to systemd and is left in place
+    let mut instance = instance::acquire(&socket_path, args.replace).await?;
+    let listener = match activated {
+        Some(listener) => {
+            tracing::info!("Using socket {} from systemd", socket_path.display());
+            UnixListener::from_std(listener)?
         }
+        None => instance.bind(args.replace).await?,
+    };
+    start(
+        listener,
+        config,
+        follow_rules,
+        &state::path_for(&socket_path),
+    )
+    .await
+}
+
+/// Re-run this executable as a detached `daemon --foreground` and wait until it accepts connections
+/// Its stderr goes nowhere, so without a log file it logs next to the socket
+async fn spawn_background(socket_path: &Path, log_file: Option<PathBuf>) -> Result<()> {
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    command
+        .args(std::env::args_os().skip(1))
+        .arg("--foreground");
+    let log_file = log_file.unwrap_or_else(|| {
+        let default = socket_path.with_extension("log");
+        command.arg("--log-file").arg(&default);
+        default
     });
+    let mut child = command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .process_group(0)
+        .spawn()
+        .context("Failed to start background daemon")?;
+
+    for _ in 0..50 {
+        if let Some(status) = child.try_wait()? {
+            anyhow::bail!(
+                "Background daemon exited during startup ({status}); see {} or run with --foreground",
+                log_file.display()
+            );
+        }
+        // With --replace the old daemon may still answer, so wait until the child owns the lock
+        let owns_lock = instance::recorded_pid(socket_path) == Some(child.id());
+        if owns_lock && UnixStream::connect(socket_path).await.is_ok() {
+            println!(
+                "nsticky daemon started in the background (pid {}), logging to {}.",
+                child.id(),
+                log_file.display()
+            );
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    anyhow::bail!(
+        "Background daemon (pid {}) did not open {} in time",
+        child.id(),
+        socket_path.display()
+    )
+}
+
+/// Daemon-wide handles shared by the CLI server and the niri watcher
+#[derive(Clone)]
+struct Shared {
+    business_logic: BusinessLogic,
+    /// Receives the `shutdown` request, carrying its staged-window restore override
+    shutdown: mpsc::Sender<Option<bool>>,
+    /// Held for reading while a request or event is processed; shutdown takes it
+    /// for writing so in-flight window moves finish and no new ones start
+    operations: Arc<RwLock<()>>,
+    /// Whether the watcher is subscribed to niri's event stream
+    watcher_connected: Arc<AtomicBool>,
+}
+
+async fn start(
+    listener: UnixListener,
+    config: Config,
+    follow_rules: Vec<(Selector, FollowScope)>,
+    state_path: &Path,
+) -> Result<()> {
+    let sticky_windows = Arc::new(Mutex::new(WindowSet::default()));
+    let staged_set = Arc::new(Mutex::new(WindowSet::default()));
+    let focus_history = Arc::new(Mutex::new(VecDeque::new()));
+    let business_logic = BusinessLogic::new(
+        sticky_windows,
+        staged_set,
+        focus_history,
+        follow_rules,
+        &config,
+    );
+
+    match state::load(state_path) {
+        Ok(Some(saved)) if saved.niri_socket != state::niri_session() => {
+            tracing::info!(
+                "Ignoring state saved in another niri session ({})",
+                saved
+                    .niri_socket
+                    .as_deref()
+                    .map_or("unknown".into(), Path::to_string_lossy)
+            );
+        }
+        Ok(Some(saved)) => match business_logic.restore_state(&saved).await {
+            Ok((sticky, staged)) => {
+                tracing::info!("Restored {sticky} sticky and {staged} staged windows");
+            }
+            Err(e) => tracing::warn!("Failed to restore saved state: {e:#}"),
+        },
+        Ok(None) => {}
+        Err(e) => tracing::warn!("Ignoring saved state: {e:#}"),
+    }
 
-    let watcher_business_logic = business_logic.clone();
+    let (shutdown_tx, mut shutdown_rx) = mpsc::channel(1);
+    let shared = Shared {
+        business_logic,
+        shutdown: shutdown_tx,
+        operations: Arc::new(RwLock::new(())),
+        watcher_connected: Arc::new(AtomicBool::new(false)),
+    };
+
+    let cli_shared = shared.clone();
     tokio::spawn(async move {
-        if let Err(_e) = run_watcher(watcher_business_logic).await {
-            eprintln!("Watcher error: {_e:?}");
+        if let Err(e) = run_cli_server(listener, cli_shared).await {
+            tracing::error!("CLI server error: {e:?}");
         }
     });
 
-    println!("nsticky daemon started.");
-    future::pending::<()>().await;
+    let watcher_shared = shared.clone();
+    tokio::spawn(async move {
+        let result = run_watcher(watcher_shared.clone()).await;
+        watcher_shared
+            .watcher_connected
+            .store(false, Ordering::Relaxed);
+        let status = match result {
+            Ok(()) => "niri event stream closed".to_string(),
+            Err(e) => {
+                tracing::error!("Watcher error: {e:?}");
+                format!("niri event stream failed: {e:#}")
+            }
+        };
+        systemd::notify(&format!("STATUS={status}"));
+        watcher_shared
+            .business_logic
+            .emit(protocol::Event::Error { message: status });
+    });
+
+    if let Some(interval) = systemd::watchdog_interval() {
+        tokio::spawn(run_watchdog(interval, shared.watcher_connected.clone()));
+    }
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    tracing::info!("nsticky daemon started.");
+    systemd::notify("READY=1\nSTATUS=Watching niri workspaces");
+    let restore_override = tokio::select! {
+        Some(restore) = shutdown_rx.recv() => restore,
+        _ = sigterm.recv() => None,
+        _ = sigint.recv() => None,
+    };
+
+    tracing::info!("Shutting down");
+    systemd::notify("STOPPING=1\nSTATUS=Saving state");
+    let _quiesced = shared.operations.write().await;
+    shared.business_logic.end_peek().await;
+    if restore_override.unwrap_or(config.restore_staged_on_exit) {
+        restore_staged_windows(&shared.business_logic).await;
+    }
+    let saved = shared.business_logic.save_state().await;
+    if let Err(e) = state::save(state_path, &saved) {
+        tracing::error!("Failed to save state: {e:#}");
+    }
     Ok(())
 }
 
-async fn run_cli_server(business_logic: BusinessLogic) -> Result<()> {
-    let cli_socket_path = "/tmp/niri_sticky_cli.sock";
-    let _ = std::fs::remove_file(cli_socket_path);
-    let listener = UnixListener::bind(cli_socket_path)?;
+/// Ping the systemd watchdog while the niri watcher is connected
+///
+/// Without niri events sticky windows stop following, so once the watcher is
+/// gone the pings stop and systemd restarts the daemon.
+async fn run_watchdog(interval: Duration, watcher_connected: Arc<AtomicBool>) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if watcher_connected.load(Ordering::Relaxed) {
+            systemd::notify("WATCHDOG=1");
+        } else {
+            tracing::debug!("Skipping watchdog ping, niri watcher is not connected");
+        }
+    }
+}
+
+/// Bring every staged window back to the active workspace before exiting
+async fn restore_staged_windows(business_logic: &BusinessLogic) {
+    let result = match crate::system_integration::get_active_workspace_id().await {
+        Ok(ws_id) => {
+            business_logic
+                .unstage_all_windows(business_logic.destination(ws_id, None))
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(count) => tracing::info!("Restored {count} staged windows"),
+        Err(e) => tracing::warn!("Failed to restore staged windows: {e:#}"),
+    }
+}
 
+async fn run_cli_server(listener: UnixListener, shared: Shared) -> Result<()> {
+    let uid = instance::current_uid();
     loop {
         let (stream, _) = listener.accept().await?;
-        let business_logic_clone = business_logic.clone();
-        tokio::spawn(async move {
-            if let Err(_e) = handle_cli_connection(stream, business_logic_clone).await {
-                eprintln!("CLI connection error: {_e:?}");
+        // The socket is private already; also refuse other users if permissions were loosened
+        match stream.peer_cred() {
+            Ok(cred) if cred.uid() == uid => {}
+            Ok(cred) => {
+                tracing::warn!("Rejected CLI connection from uid {}", cred.uid());
+                continue;
             }
-        });
+            Err(e) => {
+                tracing::warn!("Rejected CLI connection without peer credentials: {e}");
+                continue;
+            }
+        }
+        let shared = shared.clone();
+        let span = tracing::info_span!("request", id = logging::next_id());
+        tokio::spawn(
+            async move {
+                if let Err(e) = handle_cli_connection(stream, shared).await {
+                    tracing::warn!("CLI connection error: {e:?}");
+                }
+            }
+            .instrument(span),
+        );
     }
 }
 
-async fn handle_cli_connection(stream: UnixStream, business_logic: BusinessLogic) -> Result<()> {
+async fn handle_cli_connection(stream: UnixStream, shared: Shared) -> Result<()> {
     let (reader, mut writer) = stream.into_split();
     let mut reader = BufReader::new(reader);
     let mut line = String::new();
@@ -61,79 +359,475 @@ async fn handle_cli_connection(stream: UnixStream, business_logic: BusinessLogic
     }
     let line = line.trim();
 
-    // Parse request
-    let request = match protocol::parse_request(line) {
-        Ok(req) => req,
+    // JSON requests start with '{', anything else is the legacy text protocol
+    let json = line.starts_with('{');
+    let request = if json {
+        protocol::parse_json_request(line).map_err(|e| protocol::Response::Error {
+            code: e.code,
+            message: e.message,
+        })
+    } else {
+        protocol::parse_request(line).map_err(|e| protocol::Response::Error {
+            code: protocol::ErrorCode::InvalidRequest,
+            message: e.to_string(),
+        })
+    };
+    if matches!(request, Ok(protocol::Request::Watch)) {
+        return stream_events(writer, &shared.business_logic).await;
+    }
+
+    let shutdown_requested = match request {
+        Ok(protocol::Request::Shutdown { restore_staged }) => Some(restore_staged),
+        _ => None,
+    };
+    let response = match request {
+        Ok(request) => {
+            tracing::debug!(?request, "Handling request");
+            let _operation = shared.operations.read().await;
+            handle_request(request, &shared.business_logic).await
+        }
+        Err(response) => response,
+    };
+    tracing::debug!(?response, "Responding");
+    let response_str = if json {
+        protocol::format_json_response(response)
+    } else {
+        protocol::format_response(response)
+    };
+
+    // Send response
+    writer.write_all(response_str.as_bytes()).await?;
+    writer.flush().await?;
+
+    // Only stop once the client has its answer
+    if let Some(restore_staged) = shutdown_requested {
+        let _ = shared.shutdown.try_send(restore_staged);
+    }
+
+    Ok(())
+}
+
+/// Set, clear or show the follow scope of the targeted windows
+async fn handle_scope(
+    args: protocol::ScopeArgs,
+    business_logic: &BusinessLogic,
+) -> protocol::Response {
+    let ids = match args.window_id {
+        Some(id) => vec![id],
+        None => {
+            let selector = match parse_selector(args.selector.as_deref()) {
+                Ok(selector) => selector,
+                Err(response) => return response,
+            };
+            match business_logic
+                .matching_windows(&selector, args.strategy)
+                .await
+            {
+                Ok(ids) => ids,
+                Err(e) => return protocol::Response::from_error(&e),
+            }
+        }
+    };
+
+    let scope = match FollowScope::parse(&args.only, &args.never) {
+        Ok(scope) => scope,
         Err(e) => {
-            writer
-                .write_all(format!("Error: {}\n", e).as_bytes())
-                .await?;
-            return Ok(());
+            return protocol::Response::Error {
+                code: protocol::ErrorCode::InvalidRequest,
+                message: format!("{e:#}"),
+            };
         }
     };
+    if args.clear || !scope.is_unrestricted() {
+        let scope = (!args.clear).then_some(scope);
+        if let Err(e) = business_logic.set_follow_scope(&ids, scope).await {
+            return protocol::Response::from_error(&e);
+        }
+    }
 
-    // Process request and generate response
-    let response = match request {
-        protocol::Request::Add { window_id } => {
-            match business_logic.add_sticky_window(window_id).await {
-                Ok(is_new) => {
-                    if is_new {
-                        protocol::Response::Success("Added\n".to_string())
-                    } else {
-                        protocol::Response::Success("Already in sticky list\n".to_string())
-                    }
+    match business_logic.follow_scopes(&ids).await {
+        Ok(scopes) => protocol::Response::Applied {
+            message: scopes
+                .iter()
+                .map(|(id, scope, rule)| match rule {
+                    Some(rule) => format!("Window {id} follows {scope} (follow_rule {rule})"),
+                    None => format!("Window {id} follows {scope}"),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            windows: ids,
+        },
+        Err(e) => protocol::Response::from_error(&e),
+    }
+}
+
+/// Write a snapshot and then every state change until the client disconnects
+///
+/// Deliberately not holding `operations`, so an open `watch` never delays shutdown.
+async fn stream_events(
+    mut writer: tokio::net::unix::OwnedWriteHalf,
+    business_logic: &BusinessLogic,
+) -> Result<()> {
+    let mut events = business_logic.subscribe();
+    let mut next = Some(snapshot_or_error(business_logic).await);
+    loop {
+        let event = match next.take() {
+            Some(event) => event,
+            None => match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::debug!("Watch subscriber missed {missed} events, resending snapshot");
+                    snapshot_or_error(business_logic).await
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+        };
+        let line = serde_json::to_string(&event)? + "\n";
+        if writer.write_all(line.as_bytes()).await.is_err() {
+            // The subscriber went away
+            return Ok(());
+        }
+    }
+}
+
+async fn snapshot_or_error(business_logic: &BusinessLogic) -> protocol::Event {
+    business_logic
+        .snapshot()
+        .await
+        .unwrap_or_else(|e| protocol::Event::Error {
+            message: format!("Failed to read current state: {e:#}"),
+        })
+}
+
+/// Process request and generate response
+async fn handle_request(
+    request: protocol::Request,
+    business_logic: &BusinessLogic,
+) -> protocol::Response {
+    match request {
+        protocol::Request::Handshake => {
+            protocol::Response::Data(protocol::ResponseData::Handshake {
+                daemon_version: protocol::DAEMON_VERSION.to_string(),
+                protocol_version: protocol::PROTOCOL_VERSION,
+            })
+        }
+        protocol::Request::Scope(args) => handle_scope(args, business_logic).await,
+        protocol::Request::Status => {
+            let windows = tokio::try_join!(
+                business_logic.list_sticky_windows(),
+                business_logic.list_staged_windows()
+            );
+            match windows {
+                Ok((sticky, staged)) => {
+                    protocol::Response::Data(protocol::ResponseData::Status { sticky, staged })
                 }
-                Err(e) => protocol::Response::Error(e.to_string()),
+                Err(e) => protocol::Response::from_error(&e),
             }
         }
-        protocol::Request::Remove { window_id } => {
-            match business_logic.remove_sticky_window(window_id).await {
-                Ok(was_present) => {
-                    if was_present {
-                        protocol::Response::Success("Removed\n".to_string())
-                    } else {
-                        protocol::Response::Success("Not in sticky list\n".to_string())
+        protocol::Request::Watch => protocol::Response::Error {
+            code: protocol::ErrorCode::InvalidRequest,
+            message: "watch streams events and has no single response".to_string(),
+        },
+        protocol::Request::Shutdown { .. } => {
+            protocol::Response::Success("Shutting down".to_string())
+        }
+        protocol::Request::FocusHistory => {
+            let windows = crate::system_integration::get_full_window_info()
+                .await
+                .unwrap_or_default();
+            let history = business_logic
+                .focus_history()
+                .await
+                .into_iter()
+                .map(|entry| {
+                    let window = windows.iter().find(|w| w.id == entry.id);
+                    protocol::FocusHistoryEntry {
+                        id: entry.id,
+                        app_id: window.and_then(|w| w.app_id.clone()),
+                        title: window.and_then(|w| w.title.clone()),
+                        seconds_ago: entry.at.elapsed().as_secs(),
                     }
+                })
+                .collect();
+            protocol::Response::Data(protocol::ResponseData::FocusHistory { history })
+        }
+        protocol::Request::Add(protocol::Target {
+            window_id: Some(window_id),
+            ..
+        }) => match business_logic.add_sticky_window(window_id).await {
+            Ok(is_new) => {
+                if is_new {
+                    protocol::Response::Success("Added".to_string())
+                } else {
+                    protocol::Response::Unchanged("Already in sticky list".to_string())
+                }
+            }
+            Err(e) => protocol::Response::from_error(&e),
+        },
+        protocol::Request::Add(target) => {
+            let selector = match parse_selector(target.selector.as_deref()) {
+                Ok(selector) => selector,
+                Err(response) => return response,
+            };
+            match business_logic
+                .add_sticky_matching(&selector, target.strategy)
+                .await
+            {
+                Ok(results) if results.iter().all(|(_, added)| !added) => {
+                    let ids: Vec<u64> = results.into_iter().map(|(id, _)| id).collect();
+                    protocol::Response::Unchanged(format!(
+                        "{} already in sticky list",
+                        windows_label(&ids)
+                    ))
+                }
+                Ok(results) => outcome_response(
+                    results,
+                    |w| format!("Added {w} to sticky"),
+                    |w| format!("{w} already in sticky list"),
+                ),
+                Err(e) => protocol::Response::from_error(&e),
+            }
+        }
+        protocol::Request::Remove(protocol::Target {
+            window_id: Some(window_id),
+            ..
+        }) => match business_logic.remove_sticky_window(window_id).await {
+            Ok(was_present) => {
+                if was_present {
+                    protocol::Response::Success("Removed".to_string())
+                } else {
+                    protocol::Response::Unchanged("Not in sticky list".to_string())
+                }
+            }
+            Err(e) => protocol::Response::from_error(&e),
+        },
+        protocol::Request::Remove(target) => {
+            let selector = match parse_selector(target.selector.as_deref()) {
+                Ok(selector) => selector,
+                Err(response) => return response,
+            };
+            match business_logic
+                .remove_sticky_matching(&selector, target.strategy)
+                .await
+            {
+                Ok(results) if results.iter().all(|(_, removed)| !removed) => {
+                    let ids: Vec<u64> = results.into_iter().map(|(id, _)| id).collect();
+                    protocol::Response::Unchanged(format!(
+                        "{} not in sticky list",
+                        windows_label(&ids)
+                    ))
                 }
-                Err(e) => protocol::Response::Error(e.to_string()),
+                Ok(results) => outcome_response(
+                    results,
+                    |w| format!("Removed {w} from sticky"),
+                    |w| format!("{w} not in sticky list"),
+                ),
+                Err(e) => protocol::Response::from_error(&e),
+            }
+        }
+        protocol::Request::Toggle(target) => {
+            let selector = match target.window_id {
+                Some(window_id) => Selector::Id(window_id),
+                None => match parse_selector(target.selector.as_deref()) {
+                    Ok(selector) => selector,
+                    Err(response) => return response,
+                },
+            };
+            match business_logic
+                .toggle_matching(&selector, target.strategy)
+                .await
+            {
+                Ok(results) => outcome_response(
+                    results,
+                    |w| format!("Added {w} to sticky"),
+                    |w| format!("Removed {w} from sticky"),
+                ),
+                Err(e) => protocol::Response::from_error(&e),
             }
         }
+        protocol::Request::Minimize(target) => {
+            let selector = match (target.window_id, target.selector.as_deref()) {
+                (Some(window_id), _) => Selector::Id(window_id),
+                (None, None) => Selector::Focused,
+                (None, selector) => match parse_selector(selector) {
+                    Ok(selector) => selector,
+                    Err(response) => return response,
+                },
+            };
+            match business_logic
+                .minimize_matching(&selector, target.strategy)
+                .await
+            {
+                Ok(ids) => protocol::Response::Applied {
+                    message: format!("Minimized {}", windows_label(&ids)),
+                    windows: ids,
+                },
+                Err(e) => protocol::Response::from_error(&e),
+            }
+        }
+        protocol::Request::Pop { oldest, origin } => {
+            let current_ws_id = match crate::system_integration::get_active_workspace_id().await {
+                Ok(id) => id,
+                Err(_) => {
+                    return protocol::Response::Error {
+                        code: protocol::ErrorCode::Internal,
+                        message: "Failed to get active workspace ID".to_string(),
+                    };
+                }
+            };
+            match business_logic
+                .pop_staged(oldest, business_logic.destination(current_ws_id, origin))
+                .await
+            {
+                Ok(Some(unstaged)) => unstaged_response(vec![unstaged]),
+                Ok(None) => protocol::Response::Unchanged("No staged windows".to_string()),
+                Err(e) => protocol::Response::from_error(&e),
+            }
+        }
+        protocol::Request::PeekWindow {
+            target,
+            timeout_secs,
+        } => {
+            let selector = match target.window_id {
+                Some(window_id) => Selector::Id(window_id),
+                None => match parse_selector(target.selector.as_deref()) {
+                    Ok(selector) => selector,
+                    Err(response) => return response,
+                },
+            };
+            let current_ws_id = match crate::system_integration::get_active_workspace_id().await {
+                Ok(id) => id,
+                Err(_) => {
+                    return protocol::Response::Error {
+                        code: protocol::ErrorCode::Internal,
+                        message: "Failed to get active workspace ID".to_string(),
+                    };
+                }
+            };
+            match business_logic
+                .peek_matching(
+                    &selector,
+                    target.strategy,
+                    current_ws_id,
+                    timeout_secs.map(Duration::from_secs),
+                )
+                .await
+            {
+                Ok(id) => protocol::Response::Applied {
+                    message: format!("Peeking at {}", windows_label(&[id])),
+                    windows: vec![id],
+                },
+                Err(e) => protocol::Response::from_error(&e),
+            }
+        }
+        protocol::Request::Swap(target) => {
+            let selector = match (target.window_id, target.selector.as_deref()) {
+                (Some(window_id), _) => Some(Selector::Id(window_id)),
+                (None, None) => None,
+                (None, selector) => match parse_selector(selector) {
+                    Ok(selector) => Some(selector),
+                    Err(response) => return response,
+                },
+            };
+            let current_ws_id = match crate::system_integration::get_active_workspace_id().await {
+                Ok(id) => id,
+                Err(_) => {
+                    return protocol::Response::Error {
+                        code: protocol::ErrorCode::Internal,
+                        message: "Failed to get active workspace ID".to_string(),
+                    };
+                }
+            };
+            match business_logic
+                .swap_stage(
+                    selector
+                        .as_ref()
+                        .map(|selector| (selector, target.strategy)),
+                    current_ws_id,
+                )
+                .await
+            {
+                Ok((staged, unstaged)) => protocol::Response::Applied {
+                    message: format!("Staged window {staged}, unstaged window {unstaged}"),
+                    windows: vec![staged, unstaged],
+                },
+                Err(e) => protocol::Response::from_error(&e),
+            }
+        }
+        protocol::Request::Cycle { reverse } => {
+            let current_ws_id = match crate::system_integration::get_active_workspace_id().await {
+                Ok(id) => id,
+                Err(_) => {
+                    return protocol::Response::Error {
+                        code: protocol::ErrorCode::Internal,
+                        message: "Failed to get active workspace ID".to_string(),
+                    };
+                }
+            };
+            match business_logic.cycle_stage(reverse, current_ws_id).await {
+                Ok(Some(id)) => protocol::Response::Applied {
+                    message: format!("Showing {}", windows_label(&[id])),
+                    windows: vec![id],
+                },
+                Ok(None) => protocol::Response::Unchanged("No staged windows".to_string()),
+                Err(e) => protocol::Response::from_error(&e),
+            }
+        }
+        protocol::Request::Peek { oldest } => match business_logic.peek_staged(oldest).await {
+            Ok(window) => protocol::Response::Data(protocol::ResponseData::WindowList {
+                windows: window.into_iter().collect(),
+            }),
+            Err(e) => protocol::Response::from_error(&e),
+        },
         protocol::Request::List => match business_logic.list_sticky_windows().await {
-            Ok(windows) => protocol::Response::Data(format!("{:?}\n", windows)),
-            Err(e) => protocol::Response::Error(e.to_string()),
+            Ok(windows) => protocol::Response::Data(protocol::ResponseData::WindowList { windows }),
+            Err(e) => protocol::Response::from_error(&e),
         },
+        protocol::Request::Windows { selector } => {
+            let selector = match selector {
+                Some(selector) => match parse_selector(Some(&selector)) {
+                    Ok(selector) => Some(selector),
+                    Err(response) => return response,
+                },
+                None => None,
+            };
+            match business_logic.list_all_windows(selector.as_ref()).await {
+                Ok(windows) => {
+                    protocol::Response::Data(protocol::ResponseData::WindowList { windows })
+                }
+                Err(e) => protocol::Response::from_error(&e),
+            }
+        }
         protocol::Request::ToggleActive => match business_logic.toggle_active_window().await {
             Ok(was_added) => {
                 if was_added {
-                    protocol::Response::Success("Added active window to sticky\n".to_string())
+                    protocol::Response::Success("Added active window to sticky".to_string())
                 } else {
-                    protocol::Response::Success("Removed active window from sticky\n".to_string())
+                    protocol::Response::Success("Removed active window from sticky".to_string())
                 }
             }
-            Err(e) => protocol::Response::Error(e.to_string()),
+            Err(e) => protocol::Response::from_error(&e),
         },
-        protocol::Request::ToggleAppid { appid } => {
-            match business_logic.toggle_by_appid(&appid).await {
-                Ok(was_added) => {
-                    if was_added {
-                        protocol::Response::Success("Added window to sticky\n".to_string())
-                    } else {
-                        protocol::Response::Success("Removed window from sticky\n".to_string())
-                    }
-                }
-                Err(e) => protocol::Response::Error(e.to_string()),
+        protocol::Request::ToggleAppid { appid, strategy } => {
+            let selector = Selector::App(appid);
+            match business_logic.toggle_matching(&selector, strategy).await {
+                Ok(results) => outcome_response(
+                    results,
+                    |w| format!("Added {w} to sticky"),
+                    |w| format!("Removed {w} from sticky"),
+                ),
+                Err(e) => protocol::Response::from_error(&e),
             }
         }
-        protocol::Request::ToggleTitle { title } => {
-            match business_logic.toggle_by_title(&title).await {
-                Ok(was_added) => {
-                    if was_added {
-                        protocol::Response::Success("Added window to sticky\n".to_string())
-                    } else {
-                        protocol::Response::Success("Removed window from sticky\n".to_string())
-                    }
-                }
-                Err(e) => protocol::Response::Error(e.to_string()),
+        protocol::Request::ToggleTitle { title, strategy } => {
+            let selector = Selector::Title(title);
+            match business_logic.toggle_matching(&selector, strategy).await {
+                Ok(results) => outcome_response(
+                    results,
+                    |w| format!("Added {w} to sticky"),
+                    |w| format!("Removed {w} from sticky"),
+                ),
+                Err(e) => protocol::Response::from_error(&e),
             }
         }
         protocol::Request::Stage(stage_args) => {
@@ -145,7 +839,10 @@ async fn handle_cli_connection(stream: UnixStream, business_logic: BusinessLogic
                 let active_id = match crate::system_integration::get_active_window_id().await {
                     Ok(id) => id,
                     Err(_) => {
-                        return Ok(writer.write_all(b"Failed to get active window\n").await?);
+                        return protocol::Response::Error {
+                            code: protocol::ErrorCode::Internal,
+                            message: "Failed to get active window".to_string(),
+                        };
                     }
                 };
 
@@ -155,32 +852,37 @@ async fn handle_cli_connection(stream: UnixStream, business_logic: BusinessLogic
                         match crate::system_integration::get_active_workspace_id().await {
                             Ok(id) => id,
                             Err(_) => {
-                                return Ok(writer
-                                    .write_all(b"Failed to get active workspace ID\n")
-                                    .await?);
+                                return protocol::Response::Error {
+                                    code: protocol::ErrorCode::Internal,
+                                    message: "Failed to get active workspace ID".to_string(),
+                                };
                             }
                         };
-                    match business_logic.unstage_active_window(current_ws_id).await {
-                        Ok(()) => {
-                            protocol::Response::Success("Unstaged active window\n".to_string())
-                        }
-                        Err(e) => protocol::Response::Error(e.to_string()),
+                    match business_logic
+                        .unstage_active_window(business_logic.destination(current_ws_id, None))
+                        .await
+                    {
+                        Ok(sticky) => protocol::Response::Success(format!(
+                            "Unstaged active window {}",
+                            sticky_label(sticky)
+                        )),
+                        Err(e) => protocol::Response::from_error(&e),
                     }
                 } else {
                     let is_sticky = business_logic.is_window_sticky(active_id).await;
                     if is_sticky {
-                        match business_logic.stage_window(active_id).await {
+                        match business_logic.stage_active_window().await {
                             Ok(()) => {
-                                protocol::Response::Success("Staged active window\n".to_string())
+                                protocol::Response::Success("Staged active window".to_string())
                             }
-                            Err(e) => protocol::Response::Error(e.to_string()),
+                            Err(e) => protocol::Response::from_error(&e),
                         }
                     } else {
                         match business_logic.add_sticky_window(active_id).await {
                             Ok(_) => protocol::Response::Success(
-                                "Added active window to sticky\n".to_string(),
+                                "Added active window to sticky".to_string(),
                             ),
-                            Err(e) => protocol::Response::Error(e.to_string()),
+                            Err(e) => protocol::Response::from_error(&e),
                         }
                     }
                 }
@@ -189,124 +891,344 @@ async fn handle_cli_connection(stream: UnixStream, business_logic: BusinessLogic
                 {
                     Ok(id) => id,
                     Err(_) => {
-                        return Ok(writer
-                            .write_all(b"Failed to get active workspace ID\n")
-                            .await?);
+                        return protocol::Response::Error {
+                            code: protocol::ErrorCode::Internal,
+                            message: "Failed to get active workspace ID".to_string(),
+                        };
                     }
                 };
+                let selector = Selector::App(appid);
                 match business_logic
-                    .toggle_stage_by_appid(&appid, current_ws_id)
+                    .toggle_stage_matching(
+                        &selector,
+                        stage_args.strategy,
+                        business_logic.destination(current_ws_id, None),
+                    )
                     .await
                 {
-                    Ok(()) => {
-                        protocol::Response::Success("Toggled stage status by app ID\n".to_string())
-                    }
-                    Err(e) => protocol::Response::Error(e.to_string()),
+                    Ok(results) => outcome_response(
+                        results,
+                        |w| format!("Staged {w}"),
+                        |w| format!("Unstaged {w}"),
+                    ),
+                    Err(e) => protocol::Response::from_error(&e),
                 }
             } else if let Some(title) = stage_args.title {
                 let current_ws_id = match crate::system_integration::get_active_workspace_id().await
                 {
                     Ok(id) => id,
                     Err(_) => {
-                        return Ok(writer
-                            .write_all(b"Failed to get active workspace ID\n")
-                            .await?);
+                        return protocol::Response::Error {
+                            code: protocol::ErrorCode::Internal,
+                            message: "Failed to get active workspace ID".to_string(),
+                        };
                     }
                 };
+                let selector = Selector::Title(title);
                 match business_logic
-                    .toggle_stage_by_title(&title, current_ws_id)
+                    .toggle_stage_matching(
+                        &selector,
+                        stage_args.strategy,
+                        business_logic.destination(current_ws_id, None),
+                    )
                     .await
                 {
-                    Ok(()) => {
-                        protocol::Response::Success("Toggled stage status by title\n".to_string())
+                    Ok(results) => outcome_response(
+                        results,
+                        |w| format!("Staged {w}"),
+                        |w| format!("Unstaged {w}"),
+                    ),
+                    Err(e) => protocol::Response::from_error(&e),
+                }
+            } else if stage_args.toggle || stage_args.selector.is_some() {
+                let selector = match stage_args.window_id {
+                    Some(window_id) => Selector::Id(window_id),
+                    None => match parse_selector(stage_args.selector.as_deref()) {
+                        Ok(selector) => selector,
+                        Err(response) => return response,
+                    },
+                };
+                if stage_args.toggle {
+                    let current_ws_id =
+                        match crate::system_integration::get_active_workspace_id().await {
+                            Ok(id) => id,
+                            Err(_) => {
+                                return protocol::Response::Error {
+                                    code: protocol::ErrorCode::Internal,
+                                    message: "Failed to get active workspace ID".to_string(),
+                                };
+                            }
+                        };
+                    match business_logic
+                        .toggle_stage_matching(
+                            &selector,
+                            stage_args.strategy,
+                            business_logic.destination(current_ws_id, None),
+                        )
+                        .await
+                    {
+                        Ok(results) => outcome_response(
+                            results,
+                            |w| format!("Staged {w}"),
+                            |w| format!("Unstaged {w}"),
+                        ),
+                        Err(e) => protocol::Response::from_error(&e),
+                    }
+                } else {
+                    match business_logic
+                        .stage_matching(&selector, stage_args.strategy)
+                        .await
+                    {
+                        Ok(ids) => protocol::Response::Applied {
+                            message: format!("Staged {}", windows_label(&ids)),
+                            windows: ids,
+                        },
+                        Err(e) => protocol::Response::from_error(&e),
                     }
-                    Err(e) => protocol::Response::Error(e.to_string()),
                 }
             } else if stage_args.all {
                 match business_logic.stage_all_windows().await {
-                    Ok(count) => protocol::Response::Success(format!("Staged {} windows\n", count)),
-                    Err(e) => protocol::Response::Error(e.to_string()),
+                    Ok(0) => {
+                        protocol::Response::Unchanged("No sticky windows to stage".to_string())
+                    }
+                    Ok(count) => protocol::Response::Success(format!("Staged {count} windows")),
+                    Err(e) => protocol::Response::from_error(&e),
                 }
             } else if stage_args.list {
                 match business_logic.list_staged_windows().await {
-                    Ok(windows) => protocol::Response::Data(format!("{:?}\n", windows)),
-                    Err(e) => protocol::Response::Error(e.to_string()),
+                    Ok(windows) => {
+                        protocol::Response::Data(protocol::ResponseData::WindowList { windows })
+                    }
+                    Err(e) => protocol::Response::from_error(&e),
                 }
             } else if let Some(window_id) = stage_args.window_id {
                 match business_logic.stage_window(window_id).await {
-                    Ok(()) => protocol::Response::Success("Staged window\n".to_string()),
-                    Err(e) => protocol::Response::Error(e.to_string()),
+                    Ok(()) => protocol::Response::Success("Staged window".to_string()),
+                    Err(e) => protocol::Response::from_error(&e),
                 }
             } else {
-                protocol::Response::Error("Invalid stage command".to_string())
+                protocol::Response::Error {
+                    code: protocol::ErrorCode::InvalidRequest,
+                    message: "Invalid stage command".to_string(),
+                }
             }
         }
         protocol::Request::Unstage(unstage_args) => {
             let current_ws_id = match crate::system_integration::get_active_workspace_id().await {
                 Ok(id) => id,
                 Err(_) => {
-                    return Ok(writer
-                        .write_all(b"Failed to get active workspace ID\n")
-                        .await?);
+                    return protocol::Response::Error {
+                        code: protocol::ErrorCode::Internal,
+                        message: "Failed to get active workspace ID".to_string(),
+                    };
                 }
             };
 
+            let destination = business_logic.destination(current_ws_id, unstage_args.origin);
+
             if unstage_args.all {
-                match business_logic.unstage_all_windows(current_ws_id).await {
-                    Ok(count) => {
-                        protocol::Response::Success(format!("Unstaged {} windows\n", count))
-                    }
-                    Err(e) => protocol::Response::Error(e.to_string()),
+                match business_logic.unstage_all_windows(destination).await {
+                    Ok(0) => protocol::Response::Unchanged("No staged windows".to_string()),
+                    Ok(count) => protocol::Response::Success(format!("Unstaged {count} windows")),
+                    Err(e) => protocol::Response::from_error(&e),
                 }
             } else if unstage_args.active {
-                match business_logic.unstage_active_window(current_ws_id).await {
-                    Ok(()) => protocol::Response::Success("Unstaged active window\n".to_string()),
-                    Err(e) => protocol::Response::Error(e.to_string()),
+                match business_logic.unstage_active_window(destination).await {
+                    Ok(sticky) => protocol::Response::Success(format!(
+                        "Unstaged active window {}",
+                        sticky_label(sticky)
+                    )),
+                    Err(e) => protocol::Response::from_error(&e),
                 }
-            } else if let Some(window_id) = unstage_args.window_id {
+            } else if let Some(appid) = unstage_args.appid {
+                let selector = Selector::App(appid);
                 match business_logic
-                    .unstage_window(window_id, current_ws_id)
+                    .unstage_matching(&selector, unstage_args.strategy, destination)
                     .await
                 {
-                    Ok(()) => protocol::Response::Success("Unstaged window\n".to_string()),
-                    Err(e) => protocol::Response::Error(e.to_string()),
+                    Ok(results) => unstaged_response(results),
+                    Err(e) => protocol::Response::from_error(&e),
+                }
+            } else if let Some(title) = unstage_args.title {
+                let selector = Selector::Title(title);
+                match business_logic
+                    .unstage_matching(&selector, unstage_args.strategy, destination)
+                    .await
+                {
+                    Ok(results) => unstaged_response(results),
+                    Err(e) => protocol::Response::from_error(&e),
+                }
+            } else if unstage_args.selector.is_some() {
+                let selector = match parse_selector(unstage_args.selector.as_deref()) {
+                    Ok(selector) => selector,
+                    Err(response) => return response,
+                };
+                match business_logic
+                    .unstage_matching(&selector, unstage_args.strategy, destination)
+                    .await
+                {
+                    Ok(results) => unstaged_response(results),
+                    Err(e) => protocol::Response::from_error(&e),
+                }
+            } else if let Some(window_id) = unstage_args.window_id {
+                match business_logic.unstage_window(window_id, destination).await {
+                    Ok(sticky) => unstaged_response(vec![(window_id, sticky)]),
+                    Err(e) => protocol::Response::from_error(&e),
                 }
             } else {
-                protocol::Response::Error("Invalid unstage command".to_string())
+                protocol::Response::Error {
+                    code: protocol::ErrorCode::InvalidRequest,
+                    message: "Invalid unstage command".to_string(),
+                }
             }
         }
+    }
+}
+
+/// Parse a request's selector, turning syntax errors into an invalid-request response
+fn parse_selector(selector: Option<&str>) -> Result<Selector, protocol::Response> {
+    let Some(selector) = selector else {
+        return Err(protocol::Response::Error {
+            code: protocol::ErrorCode::InvalidRequest,
+            message: "Missing window id or selector".to_string(),
+        });
     };
+    selector::parse(selector).map_err(|e| protocol::Response::Error {
+        code: protocol::ErrorCode::InvalidRequest,
+        message: e.to_string(),
+    })
+}
 
-    // Send response
-    let response_str = protocol::format_response(response);
-    writer.write_all(response_str.as_bytes()).await?;
+/// Describe affected windows, e.g. "window 12" or "windows 12, 14"
+fn windows_label(ids: &[u64]) -> String {
+    let list = ids
+        .iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if ids.len() == 1 {
+        format!("window {list}")
+    } else {
+        format!("windows {list}")
+    }
+}
 
-    Ok(())
+/// Summarise per-window results, listing the windows with each outcome
+fn outcome_response(
+    results: Vec<(u64, bool)>,
+    on: impl Fn(&str) -> String,
+    off: impl Fn(&str) -> String,
+) -> protocol::Response {
+    let turned_on: Vec<u64> = results
+        .iter()
+        .filter(|(_, on)| *on)
+        .map(|(id, _)| *id)
+        .collect();
+    let turned_off: Vec<u64> = results
+        .iter()
+        .filter(|(_, on)| !*on)
+        .map(|(id, _)| *id)
+        .collect();
+
+    let mut parts = Vec::new();
+    if !turned_on.is_empty() {
+        parts.push(on(&windows_label(&turned_on)));
+    }
+    if !turned_off.is_empty() {
+        parts.push(off(&windows_label(&turned_off)));
+    }
+
+    protocol::Response::Applied {
+        message: parts.join("; "),
+        windows: results.into_iter().map(|(id, _)| id).collect(),
+    }
+}
+
+/// How a window came back from the stage: sticky again, or a normal window it was minimized as
+fn sticky_label(sticky: bool) -> &'static str {
+    if sticky { "(sticky)" } else { "(normal)" }
+}
+
+/// Response for windows brought back from the stage, saying which are sticky again
+fn unstaged_response(results: Vec<(u64, bool)>) -> protocol::Response {
+    outcome_response(
+        results,
+        |w| format!("Unstaged {w} {}", sticky_label(true)),
+        |w| format!("Unstaged {w} {}", sticky_label(false)),
+    )
 }
 
-async fn run_watcher(business_logic: BusinessLogic) -> Result<()> {
-    let socket_path = std::env::var("NIRI_SOCKET").expect("NIRI_SOCKET env var not set");
+async fn run_watcher(shared: Shared) -> Result<()> {
+    let business_logic = &shared.business_logic;
+    let socket_path = std::env::var("NIRI_SOCKET").context("NIRI_SOCKET is not set")?;
     let stream = UnixStream::connect(&socket_path).await?;
     let (reader, mut writer) = stream.into_split();
     let mut reader = BufReader::new(reader);
 
     writer.write_all(b"\"EventStream\"\n").await?;
     writer.flush().await?;
+    shared.watcher_connected.store(true, Ordering::Relaxed);
 
     let mut line = String::new();
 
     while reader.read_line(&mut line).await? > 0 {
-        if let Ok(v) = serde_json::from_str::<Value>(&line)
-            && let Some(ws) = v.get("WorkspaceActivated")
-            && let Some(ws_id) = ws.get("id").and_then(|id| id.as_u64())
-        {
-            println!("Workspace switched to: {ws_id}");
-            if let Err(_e) = business_logic.handle_workspace_activation(ws_id).await {
-                eprintln!("Failed to handle workspace activation: {_e:?}");
-            }
-        }
+        let Ok(v) = serde_json::from_str::<Value>(&line) else {
+            line.clear();
+            continue;
+        };
+        // Named after the event's variant, e.g. `event{id=7 kind=WorkspaceActivated}`
+        let kind = v
+            .as_object()
+            .and_then(|event| event.keys().next())
+            .map(String::as_str)
+            .unwrap_or("unknown");
+        let span = tracing::info_span!("event", id = logging::next_id(), kind);
+        let _operation = shared.operations.read().await;
+        handle_event(&v, business_logic).instrument(span).await;
         line.clear();
     }
 
     Ok(())
 }
+
+/// Apply one event from niri's event stream
+async fn handle_event(v: &Value, business_logic: &BusinessLogic) {
+    if let Some(ws) = v.get("WorkspaceActivated")
+        && let Some(ws_id) = ws.get("id").and_then(|id| id.as_u64())
+    {
+        tracing::debug!("Workspace switched to: {ws_id}");
+        if let Err(e) = business_logic.handle_workspace_activation(ws_id).await {
+            tracing::warn!("Failed to handle workspace activation: {e:?}");
+            business_logic.emit(protocol::Event::Error {
+                message: format!("Failed to follow workspace {ws_id}: {e:#}"),
+            });
+        }
+    } else if let Some(focus) = v.get("WindowFocusChanged") {
+        let win_id = focus.get("id").and_then(|id| id.as_u64());
+        if business_logic.is_own_focus(win_id).await {
+            tracing::debug!("Ignoring focus change made by the daemon");
+            return;
+        }
+        if let Some(win_id) = win_id {
+            business_logic.record_focus(win_id).await;
+        }
+        business_logic.handle_focus_change(win_id).await;
+    } else if let Some(closed) = v.get("WindowClosed") {
+        if let Some(win_id) = closed.get("id").and_then(|id| id.as_u64()) {
+            business_logic.forget_window(win_id).await;
+        }
+    } else if let Some(windows) = v
+        .get("WindowsChanged")
+        .and_then(|changed| changed.get("windows"))
+        .and_then(|windows| windows.as_array())
+    {
+        // Sent once when the stream starts: seed the history with the focused window
+        let focused = windows
+            .iter()
+            .find(|w| w.get("is_focused").and_then(|f| f.as_bool()) == Some(true))
+            .and_then(|w| w.get("id").and_then(|id| id.as_u64()));
+        if let Some(win_id) = focused {
+            business_logic.record_focus(win_id).await;
+        }
+    }
+}
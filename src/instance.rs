@@ -0,0 +1,339 @@
+use anyhow::{Context, Result};
+use std::fs::{DirBuilder, File, OpenOptions, Permissions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+use crate::protocol::{JsonRequest, PROTOCOL_VERSION, Request};
+
+/// How long `--replace` and `daemon stop` wait for the running daemon to exit
+pub const HANDOVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Control socket path shared by CLI and daemon
+///
+/// `NSTICKY_SOCKET` wins over the configured path. By default the socket lives in
+/// `$XDG_RUNTIME_DIR`, named after the niri instance in `NIRI_SOCKET` so nested or
+/// parallel niri sessions each get their own daemon.
+pub fn socket_path(configured: Option<PathBuf>) -> PathBuf {
+    if let Some(path) = std::env::var_os("NSTICKY_SOCKET").filter(|p| !p.is_empty()) {
+        return PathBuf::from(path);
+    }
+    if let Some(path) = configured {
+        return path;
+    }
+
+    let dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(fallback_dir);
+    let niri_instance = std::env::var_os("NIRI_SOCKET").and_then(|niri| {
+        Path::new(&niri)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+    });
+    match niri_instance {
+        Some(instance) => dir.join(format!("nsticky-{instance}.sock")),
+        None => dir.join("nsticky.sock"),
+    }
+}
+
+/// Per-user socket directory in the shared temp directory, when there is no `XDG_RUNTIME_DIR`
+fn fallback_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("nsticky-{}", current_uid()))
+}
+
+/// Create a directory only we can use, or check that the existing one is
+///
+/// Anyone can create the fallback directory's path in the shared temp directory
+/// first, so a symlink, a directory owned by someone else or one open to others
+/// is refused rather than trusted with the socket.
+fn ensure_private_dir(dir: &Path) -> Result<()> {
+    match DirBuilder::new().mode(0o700).create(dir) {
+        // The umask may have dropped bits from the mode
+        Ok(()) => std::fs::set_permissions(dir, Permissions::from_mode(0o700))?,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e).with_context(|| format!("Failed to create {}", dir.display())),
+    }
+
+    let metadata = std::fs::symlink_metadata(dir)
+        .with_context(|| format!("Failed to inspect {}", dir.display()))?;
+    if !metadata.file_type().is_dir() {
+        anyhow::bail!("{} is not a directory", dir.display());
+    }
+    if metadata.uid() != current_uid() {
+        anyhow::bail!(
+            "{} is owned by uid {}, not {}",
+            dir.display(),
+            metadata.uid(),
+            current_uid()
+        );
+    }
+    if metadata.mode() & 0o777 != 0o700 {
+        anyhow::bail!(
+            "{} has mode {:o}, expected 700",
+            dir.display(),
+            metadata.mode() & 0o777
+        );
+    }
+    Ok(())
+}
+
+/// Real user id of this process
+pub fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail
+    unsafe { libc::getuid() }
+}
+
+/// Ownership of a control socket, held for the daemon's lifetime
+///
+/// The lock file next to the socket is locked while the daemon runs, so a
+/// second daemon can tell a live instance from a stale socket file. The lock is
+/// released by the kernel when the process exits, however it exits.
+pub struct Instance {
+    _lock: File,
+    socket_path: PathBuf,
+    socket_ino: Option<u64>,
+}
+
+impl Instance {
+    /// Bind the control socket, removing a stale socket file first
+    pub async fn bind(&mut self, replace: bool) -> Result<UnixListener> {
+        if UnixStream::connect(&self.socket_path).await.is_ok() {
+            // Holding the lock, so this is a daemon that predates lock files
+            if !replace {
+                anyhow::bail!(
+                    "{} is in use by another process; use --replace to take over",
+                    self.socket_path.display()
+                );
+            }
+            tracing::warn!(
+                "Taking over {} from an unlocked daemon",
+                self.socket_path.display()
+            );
+        } else if self.socket_path.exists() {
+            tracing::info!("Removing stale socket {}", self.socket_path.display());
+        }
+        let _ = std::fs::remove_file(&self.socket_path);
+
+        let listener = UnixListener::bind(&self.socket_path)
+            .with_context(|| format!("Failed to bind {}", self.socket_path.display()))?;
+        std::fs::set_permissions(&self.socket_path, Permissions::from_mode(0o600))?;
+        self.socket_ino = std::fs::metadata(&self.socket_path).map(|m| m.ino()).ok();
+        Ok(listener)
+    }
+}
+
+impl Drop for Instance {
+    /// Remove the socket file, unless another daemon has replaced it meanwhile
+    fn drop(&mut self) {
+        let current = std::fs::metadata(&self.socket_path).map(|m| m.ino()).ok();
+        if self.socket_ino.is_some() && current == self.socket_ino {
+            let _ = std::fs::remove_file(&self.socket_path);
+        }
+    }
+}
+
+/// Lock file guarding a control socket, e.g. `nsticky.sock` -> `nsticky.lock`
+fn lock_path(socket_path: &Path) -> PathBuf {
+    socket_path.with_extension("lock")
+}
+
+fn open_lock(socket_path: &Path) -> Result<File> {
+    let path = lock_path(socket_path);
+    if let Some(dir) = path.parent() {
+        // Fallback directory outside XDG_RUNTIME_DIR; keep it private like the runtime dir
+        if dir == fallback_dir() {
+            ensure_private_dir(dir)?;
+        } else if !dir.exists() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+    }
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(&path)
+        .with_context(|| format!("Failed to open lock file {}", path.display()))
+}
+
+/// Try to lock, returning `Ok(false)` if another process holds the lock
+fn try_lock(file: &File) -> Result<bool> {
+    match file.try_lock() {
+        Ok(()) => Ok(true),
+        Err(TryLockError::WouldBlock) => Ok(false),
+        Err(TryLockError::Error(e)) => Err(e).context("Failed to lock"),
+    }
+}
+
+/// Pid recorded in a lock file by the daemon holding it
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut text = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut text).ok()?;
+    text.trim().parse().ok()
+}
+
+/// Pid last recorded in the lock file, read without touching the lock
+pub fn recorded_pid(socket_path: &Path) -> Option<u32> {
+    let text = std::fs::read_to_string(lock_path(socket_path)).ok()?;
+    text.trim().parse().ok()
+}
+
+/// Pid of the daemon currently owning the socket, if one is running
+pub fn running_pid(socket_path: &Path) -> Result<Option<u32>> {
+    let mut lock = open_lock(socket_path)?;
+    if try_lock(&lock)? {
+        return Ok(None);
+    }
+    Ok(Some(read_pid(&mut lock).unwrap_or_default()))
+}
+
+/// Become the single daemon for a socket
+///
+/// With `replace`, a running daemon is asked to shut down and we wait for it to
+/// exit; otherwise a running daemon is an error.
+pub async fn acquire(socket_path: &Path, replace: bool) -> Result<Instance> {
+    let mut lock = open_lock(socket_path)?;
+
+    if !try_lock(&lock)? {
+        let pid = read_pid(&mut lock).unwrap_or_default();
+        if !replace {
+            anyhow::bail!(
+                "nsticky daemon already running (pid {pid}) on {}; use --replace to take over",
+                socket_path.display()
+            );
+        }
+
+        tracing::info!("Asking running daemon (pid {pid}) to hand over");
+        if let Err(e) = request_shutdown(socket_path).await {
+            tracing::warn!("Failed to ask daemon (pid {pid}) to shut down: {e:#}");
+        }
+        let deadline = tokio::time::Instant::now() + HANDOVER_TIMEOUT;
+        while !try_lock(&lock)? {
+            if tokio::time::Instant::now() >= deadline {
+                anyhow::bail!(
+                    "Running daemon (pid {pid}) did not exit within {HANDOVER_TIMEOUT:?}"
+                );
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    lock.set_len(0)?;
+    lock.rewind()?;
+    writeln!(lock, "{}", std::process::id())?;
+
+    Ok(Instance {
+        _lock: lock,
+        socket_path: socket_path.to_path_buf(),
+        socket_ino: None,
+    })
+}
+
+/// Ask the daemon on a socket to shut down
+async fn request_shutdown(socket_path: &Path) -> Result<()> {
+    let stream = UnixStream::connect(socket_path).await?;
+    let (reader, mut writer) = stream.into_split();
+    let request = serde_json::to_string(&JsonRequest {
+        version: PROTOCOL_VERSION,
+        // Handing over, so staged windows stay where the new daemon will find them
+        request: Request::Shutdown {
+            restore_staged: Some(false),
+        },
+    })?;
+    writer.write_all(format!("{request}\n").as_bytes()).await?;
+
+    let mut response = String::new();
+    BufReader::new(reader).read_line(&mut response).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh directory for one test, removed again when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("nsticky-test-{}-{name}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn private_dir_is_created_or_checked() {
+        let temp = TempDir::new("private-dir");
+        let dir = temp.0.join("runtime");
+        ensure_private_dir(&dir).unwrap();
+        assert_eq!(std::fs::metadata(&dir).unwrap().mode() & 0o777, 0o700);
+        ensure_private_dir(&dir).unwrap();
+
+        std::fs::set_permissions(&dir, Permissions::from_mode(0o755)).unwrap();
+        assert!(ensure_private_dir(&dir).is_err());
+
+        let link = temp.0.join("link");
+        std::os::unix::fs::symlink(&dir, &link).unwrap();
+        std::fs::set_permissions(&dir, Permissions::from_mode(0o700)).unwrap();
+        assert!(ensure_private_dir(&link).is_err());
+    }
+
+    #[tokio::test]
+    async fn second_daemon_is_refused_while_the_lock_is_held() {
+        let temp = TempDir::new("second-lock");
+        let socket = temp.0.join("nsticky.sock");
+        let _first = acquire(&socket, false).await.unwrap();
+
+        let err = acquire(&socket, false).await.err().unwrap();
+        assert!(err.to_string().contains("already running"), "{err:#}");
+        assert_eq!(running_pid(&socket).unwrap(), Some(std::process::id()));
+    }
+
+    #[tokio::test]
+    async fn stale_socket_is_replaced() {
+        let temp = TempDir::new("stale-socket");
+        let socket = temp.0.join("nsticky.sock");
+        // A daemon that died without cleaning up leaves its socket file behind
+        drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+        assert!(UnixStream::connect(&socket).await.is_err());
+
+        let mut instance = acquire(&socket, false).await.unwrap();
+        let listener = instance.bind(false).await.unwrap();
+        UnixStream::connect(&socket).await.unwrap();
+        drop(listener);
+    }
+
+    #[tokio::test]
+    async fn lock_and_socket_are_released_on_drop() {
+        let temp = TempDir::new("release");
+        let socket = temp.0.join("nsticky.sock");
+        let mut instance = acquire(&socket, false).await.unwrap();
+        let _listener = instance.bind(false).await.unwrap();
+
+        drop(instance);
+        assert!(!socket.exists());
+        assert_eq!(running_pid(&socket).unwrap(), None);
+        acquire(&socket, false).await.unwrap();
+    }
+}
@@ -0,0 +1,70 @@
+use anyhow::{Context, Result};
+use std::fs::OpenOptions;
+use std::io::IsTerminal;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::filter::LevelFilter;
+
+use crate::config::{LogFormat, LogLevel};
+
+/// Environment variable with extra filter directives, e.g. `nsticky::business=trace`
+pub const LOG_ENV: &str = "NSTICKY_LOG";
+
+/// Resolved logging settings of the daemon
+#[derive(Debug, Default)]
+pub struct LogSettings {
+    /// Level of every module without its own directive
+    pub level: LogLevel,
+    /// Comma-separated `target=level` directives
+    pub filter: Option<String>,
+    pub format: LogFormat,
+    /// Append to this file instead of writing to stderr
+    pub file: Option<PathBuf>,
+}
+
+/// Install the global subscriber
+///
+/// Directives in `NSTICKY_LOG` are applied after the configured filter, so they
+/// win for the modules they name.
+pub fn init(settings: LogSettings) -> Result<()> {
+    let mut directives: Vec<String> = settings.filter.into_iter().collect();
+    directives.extend(std::env::var(LOG_ENV).ok().filter(|env| !env.is_empty()));
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::from(settings.level).into())
+        .parse(directives.join(","))
+        .context("Invalid log filter")?;
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match (settings.file, settings.format) {
+        (Some(path), format) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .mode(0o600)
+                .open(&path)
+                .with_context(|| format!("Failed to open log file {}", path.display()))?;
+            let builder = builder.with_writer(file).with_ansi(false);
+            match format {
+                LogFormat::Text => builder.init(),
+                LogFormat::Json => builder.json().init(),
+            }
+        }
+        (None, LogFormat::Text) => builder
+            .with_writer(std::io::stderr)
+            .with_ansi(std::io::stderr().is_terminal())
+            .init(),
+        (None, LogFormat::Json) => builder.json().with_writer(std::io::stderr).init(),
+    }
+    Ok(())
+}
+
+/// Next correlation id for a CLI request or niri event span
+///
+/// Every log line emitted while handling the request or event, including the
+/// niri calls it makes, carries the id of its span.
+pub fn next_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
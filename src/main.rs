@@ -1,22 +1,45 @@
 mod business;
 mod cli;
+mod config;
 mod daemon;
+mod instance;
+mod logging;
+mod output;
 mod protocol;
+mod scope;
+mod selector;
+mod state;
 mod system_integration;
+mod systemd;
+mod wire;
 
 use anyhow::Result;
-use std::{collections::HashSet, env, sync::Arc};
-use tokio::sync::Mutex;
+use clap::Parser;
+use std::process::ExitCode;
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    if env::args().nth(1).is_some() {
-        // Run in CLI mode
-        return cli::run_cli().await;
+async fn main() -> Result<ExitCode> {
+    let cli = cli::Cli::parse();
+    match cli.command {
+        Some(cli::Commands::Daemon(args)) if args.action.is_none() => {
+            daemon::run(daemon::DaemonArgs {
+                config: cli.config,
+                socket: cli.socket,
+                ..args
+            })
+            .await?
+        }
+        // Bare `nsticky` keeps working for `spawn-at-startup "nsticky"`
+        None => {
+            daemon::run(daemon::DaemonArgs {
+                config: cli.config,
+                socket: cli.socket,
+                foreground: true,
+                ..Default::default()
+            })
+            .await?
+        }
+        Some(command) => return Ok(cli::run_cli(command, cli.config.as_deref(), cli.socket).await),
     }
-
-    // Run in daemon mode
-    let sticky_windows = Arc::new(Mutex::new(HashSet::<u64>::new()));
-
-    daemon::start(sticky_windows).await
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,302 @@
+use anyhow::Result;
+
+use crate::protocol::WindowEntry;
+
+/// Longest title shown in the table before it is truncated
+const TITLE_WIDTH: usize = 40;
+
+/// How the CLI renders a list of windows
+#[derive(Debug, Clone)]
+pub enum OutputFormat {
+    /// Aligned, human readable table
+    Table,
+    /// Pretty-printed JSON array
+    Json,
+    /// One window id per line
+    IdsOnly,
+    /// One line per window, placeholders like `{id}` or `{app_id}` filled in
+    Template(String),
+}
+
+/// Render windows in the given format, one trailing newline included
+pub fn render_windows(windows: &[WindowEntry], format: &OutputFormat) -> Result<String> {
+    let out = match format {
+        OutputFormat::Table => render_table(windows),
+        OutputFormat::Json => format!("{}\n", serde_json::to_string_pretty(windows)?),
+        OutputFormat::IdsOnly => windows.iter().map(|w| format!("{}\n", w.id)).collect(),
+        OutputFormat::Template(template) => {
+            let mut out = String::new();
+            for window in windows {
+                out.push_str(&fill_template(template, |name| field(window, name))?);
+                out.push('\n');
+            }
+            out
+        }
+    };
+    Ok(out)
+}
+
+fn render_table(windows: &[WindowEntry]) -> String {
+    if windows.is_empty() {
+        return "No windows\n".to_string();
+    }
+
+    let header = [
+        "ID",
+        "APP ID",
+        "TITLE",
+        "WORKSPACE",
+        "OUTPUT",
+        "FLOATING",
+        "STATE",
+        "SINCE",
+    ]
+    .map(String::from);
+    let rows: Vec<[String; 8]> = windows
+        .iter()
+        .map(|w| {
+            [
+                w.id.to_string(),
+                w.app_id.clone().unwrap_or_else(|| "-".to_string()),
+                truncate(w.title.as_deref().unwrap_or("-"), TITLE_WIDTH),
+                workspace_label(w),
+                w.output.clone().unwrap_or_else(|| "-".to_string()),
+                if w.is_floating { "yes" } else { "no" }.to_string(),
+                w.state.to_string(),
+                w.since_secs
+                    .map(format_duration)
+                    .unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.each_ref().map(|h| h.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut out = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        out.push_str(line.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Fill `{name}` placeholders via `value`; `{{` and `}}` are literal braces
+fn fill_template(template: &str, value: impl Fn(&str) -> Result<String>) -> Result<String> {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.next_if_eq(&'{').is_some() => out.push('{'),
+            '}' if chars.next_if_eq(&'}').is_some() => out.push('}'),
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => anyhow::bail!("Unterminated placeholder '{{{name}' in format"),
+                    }
+                }
+                out.push_str(&value(&name)?);
+            }
+            _ => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+fn field(window: &WindowEntry, name: &str) -> Result<String> {
+    let value = match name {
+        "id" => window.id.to_string(),
+        "app_id" => window.app_id.clone().unwrap_or_default(),
+        "title" => window.title.clone().unwrap_or_default(),
+        "workspace" => workspace_label(window),
+        "workspace_id" => window
+            .workspace_id
+            .map(|id| id.to_string())
+            .unwrap_or_default(),
+        "output" => window.output.clone().unwrap_or_default(),
+        "floating" => window.is_floating.to_string(),
+        "state" => window.state.to_string(),
+        "since" => window.since_secs.map(format_duration).unwrap_or_default(),
+        "since_secs" => window.since_secs.map(|s| s.to_string()).unwrap_or_default(),
+        _ => anyhow::bail!(
+            "Unknown placeholder '{{{name}}}' (expected id, app_id, title, workspace, workspace_id, output, floating, state, since or since_secs)"
+        ),
+    };
+    Ok(value)
+}
+
+/// Status bar text used unless a format is given
+pub const DEFAULT_STATUS_FORMAT: &str = "{sticky} / {staged}";
+
+/// Status bar text: `{sticky}`, `{staged}` and `{total}` replaced by window counts
+pub fn render_status_text(
+    sticky: &[WindowEntry],
+    staged: &[WindowEntry],
+    format: &str,
+) -> Result<String> {
+    fill_template(format, |name| {
+        let count = match name {
+            "sticky" => sticky.len(),
+            "staged" => staged.len(),
+            "total" => sticky.len() + staged.len(),
+            _ => {
+                anyhow::bail!("Unknown placeholder '{{{name}}}' (expected sticky, staged or total)")
+            }
+        };
+        Ok(count.to_string())
+    })
+}
+
+/// Waybar custom-module JSON: the status text, a tooltip listing the windows,
+/// CSS classes and an `alt` for `format-icons`
+pub fn render_waybar(
+    sticky: &[WindowEntry],
+    staged: &[WindowEntry],
+    format: &str,
+) -> Result<String> {
+    let mut tooltip = Vec::new();
+    for (heading, windows) in [("Sticky", sticky), ("Staged", staged)] {
+        if windows.is_empty() {
+            continue;
+        }
+        tooltip.push(format!("{heading}:"));
+        tooltip.extend(windows.iter().map(|w| {
+            let app_id = w.app_id.as_deref().unwrap_or("-");
+            let title = truncate(w.title.as_deref().unwrap_or("-"), TITLE_WIDTH);
+            // Waybar renders tooltips as Pango markup
+            escape_markup(&format!("  {app_id}: {title}"))
+        }));
+    }
+    if tooltip.is_empty() {
+        tooltip.push("No sticky or staged windows".to_string());
+    }
+
+    let alt = match (sticky.is_empty(), staged.is_empty()) {
+        (true, true) => "empty",
+        (false, true) => "sticky",
+        (true, false) => "staged",
+        (false, false) => "mixed",
+    };
+    let mut class = Vec::new();
+    if !sticky.is_empty() {
+        class.push("sticky");
+    }
+    if !staged.is_empty() {
+        class.push("staged");
+    }
+    if class.is_empty() {
+        class.push("empty");
+    }
+
+    Ok(serde_json::json!({
+        "text": render_status_text(sticky, staged, format)?,
+        "tooltip": tooltip.join("\n"),
+        "class": class,
+        "alt": alt,
+    })
+    .to_string())
+}
+
+fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Workspace name if it has one, otherwise its index
+fn workspace_label(window: &WindowEntry) -> String {
+    match (&window.workspace_name, window.workspace_idx) {
+        (Some(name), _) => name.clone(),
+        (None, Some(idx)) => idx.to_string(),
+        (None, None) => "-".to_string(),
+    }
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut out: String = text.chars().take(width - 1).collect();
+    out.push('…');
+    out
+}
+
+/// Compact duration such as `42s`, `5m`, `3h` or `2d`
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::WindowState;
+
+    fn entry() -> WindowEntry {
+        WindowEntry {
+            id: 12,
+            app_id: Some("kitty".to_string()),
+            title: Some("zsh".to_string()),
+            workspace_id: Some(3),
+            workspace_idx: Some(1),
+            workspace_name: None,
+            output: Some("DP-1".to_string()),
+            is_floating: true,
+            state: WindowState::Staged,
+            since_secs: Some(125),
+        }
+    }
+
+    #[test]
+    fn template_fills_placeholders() {
+        let format = OutputFormat::Template("{id} {app_id} {{{state}}} {since}".to_string());
+        assert_eq!(
+            render_windows(&[entry()], &format).unwrap(),
+            "12 kitty {staged} 2m\n"
+        );
+        let format = OutputFormat::Template("{nope}".to_string());
+        assert!(render_windows(&[entry()], &format).is_err());
+    }
+
+    #[test]
+    fn waybar_output_counts_and_lists_windows() {
+        let mut sticky = entry();
+        sticky.title = Some("<b> & co".to_string());
+        let out = render_waybar(&[sticky], &[], "📌 {sticky} / 📦 {staged}").unwrap();
+        let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(json["text"], "📌 1 / 📦 0");
+        assert_eq!(json["tooltip"], "Sticky:\n  kitty: &lt;b&gt; &amp; co");
+        assert_eq!(json["class"], serde_json::json!(["sticky"]));
+        assert_eq!(json["alt"], "sticky");
+        assert!(render_status_text(&[], &[], "{nope}").is_err());
+    }
+
+    #[test]
+    fn table_columns_are_aligned() {
+        let mut long = entry();
+        long.id = 1234;
+        long.title = Some("x".repeat(60));
+        let table = render_windows(&[entry(), long], &OutputFormat::Table).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        let column = lines[0].find("APP ID").unwrap();
+        assert!(lines[1..].iter().all(|l| l[column..].starts_with("kitty")));
+        assert!(lines[2].contains('…'));
+    }
+}
@@ -1,266 +1,591 @@
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::iter::Peekable;
+
+/// Version of the JSON control protocol spoken over the CLI socket
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Version of the running daemon, reported in the handshake
+pub const DAEMON_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 /// Define request types
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
 pub enum Request {
-    Add { window_id: u64 },
-    Remove { window_id: u64 },
+    Handshake,
+    /// Stop the daemon, e.g. to hand over to a replacement
+    Shutdown {
+        /// Bring staged windows back first; `None` uses the daemon's config
+        #[serde(default)]
+        restore_staged: Option<bool>,
+    },
+    FocusHistory,
+    Add(Target),
+    Remove(Target),
     List,
+    /// Every niri window, optionally narrowed by a selector
+    Windows {
+        #[serde(default)]
+        selector: Option<String>,
+    },
     ToggleActive,
-    ToggleAppid { appid: String },
-    ToggleTitle { title: String },
+    Toggle(Target),
+    ToggleAppid {
+        appid: String,
+        #[serde(default)]
+        strategy: MatchStrategy,
+    },
+    ToggleTitle {
+        title: String,
+        #[serde(default)]
+        strategy: MatchStrategy,
+    },
     Stage(StageArgs),
     Unstage(UnstageArgs),
+    /// Stage any window, sticky or not; no window id or selector means the focused window
+    Minimize(Target),
+    /// Unstage the most recently staged window, or the oldest
+    Pop {
+        #[serde(default)]
+        oldest: bool,
+        /// Return to where the window was staged from; `None` uses the daemon's config
+        #[serde(default)]
+        origin: Option<bool>,
+    },
+    /// The staged window `Pop` would bring back
+    Peek {
+        #[serde(default)]
+        oldest: bool,
+    },
+    /// Show a staged window on the current workspace until it loses focus
+    PeekWindow {
+        #[serde(flatten)]
+        target: Target,
+        /// Seconds before the window goes back; `None` uses the daemon's config
+        #[serde(default)]
+        timeout_secs: Option<u64>,
+    },
+    /// Stage the focused sticky window and bring back a staged one in one step;
+    /// no window id or selector brings back the most recently staged window
+    Swap(Target),
+    /// Show the next staged window, sending the one shown before back to the stage
+    Cycle {
+        #[serde(default)]
+        reverse: bool,
+    },
+    /// Set, clear or show which workspaces sticky windows follow onto
+    Scope(ScopeArgs),
+    /// Keep the connection open and stream `Event`s, starting with a snapshot
+    Watch,
+    /// Sticky and staged windows, for status bars
+    Status,
+}
+
+/// Window(s) a command acts on: a bare window id or a selector expression
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Target {
+    pub window_id: Option<u64>,
+    pub selector: Option<String>,
+    pub strategy: MatchStrategy,
+}
+
+impl Target {
+    /// Target from a command-line argument: plain numbers are window ids, anything else a selector
+    pub fn from_arg(arg: String, strategy: MatchStrategy) -> Self {
+        match arg.parse::<u64>() {
+            Ok(id) => Target {
+                window_id: Some(id),
+                selector: None,
+                strategy,
+            },
+            Err(_) => Target {
+                window_id: None,
+                selector: Some(arg),
+                strategy,
+            },
+        }
+    }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct StageArgs {
     pub window_id: Option<u64>,
+    pub selector: Option<String>,
+    pub toggle: bool,
     pub all: bool,
     pub list: bool,
     pub active: bool,
     pub appid: Option<String>,
     pub title: Option<String>,
+    pub strategy: MatchStrategy,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct UnstageArgs {
     pub window_id: Option<u64>,
+    pub selector: Option<String>,
     pub all: bool,
     pub active: bool,
     pub appid: Option<String>,
     pub title: Option<String>,
+    pub strategy: MatchStrategy,
+    /// Return windows to where they were staged from; `None` uses the daemon's config
+    pub origin: Option<bool>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScopeArgs {
+    pub window_id: Option<u64>,
+    pub selector: Option<String>,
+    pub strategy: MatchStrategy,
+    /// Workspace patterns to follow onto exclusively, e.g. `1-4` or `dev*`
+    pub only: Vec<String>,
+    /// Workspace patterns never to follow onto
+    pub never: Vec<String>,
+    /// Drop the window's own scope, falling back to config rules
+    pub clear: bool,
+}
+
+/// Which of several windows matching an app ID or title a command acts on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchStrategy {
+    /// First match in niri's window list
+    First,
+    /// Every match
+    All,
+    /// The match that currently has focus
+    Focused,
+    /// The most recently focused match
+    #[default]
+    Mru,
+}
+
+impl MatchStrategy {
+    /// Strategy selected by a text-protocol flag such as `--first`
+    fn from_flag(flag: &str) -> Option<Self> {
+        match flag {
+            "--first" => Some(MatchStrategy::First),
+            "--all" => Some(MatchStrategy::All),
+            "--focused" => Some(MatchStrategy::Focused),
+            "--mru" => Some(MatchStrategy::Mru),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for MatchStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            MatchStrategy::First => "first",
+            MatchStrategy::All => "matching",
+            MatchStrategy::Focused => "focused",
+            MatchStrategy::Mru => "recently focused",
+        })
+    }
+}
+
+/// JSON request envelope: `{"version": 1, "command": "add", "window_id": 42}`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JsonRequest {
+    pub version: u32,
+    #[serde(flatten)]
+    pub request: Request,
+}
+
+/// Machine-readable error codes returned in JSON responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request could not be parsed or is missing arguments
+    InvalidRequest,
+    /// The client speaks a newer protocol than the daemon
+    UnsupportedVersion,
+    /// The target window does not exist or matched nothing
+    NotFound,
+    /// The window is not in a state that allows the operation
+    InvalidState,
+    /// Any other failure, e.g. talking to niri
+    Internal,
+}
+
+/// Error carrying a protocol error code, raised by the business layer
+#[derive(Debug)]
+pub struct CommandError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl CommandError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Build a `NotFound` error
+pub fn not_found(message: impl Into<String>) -> anyhow::Error {
+    CommandError::new(ErrorCode::NotFound, message).into()
+}
+
+/// Build an `InvalidRequest` error
+pub fn invalid_request(message: impl Into<String>) -> anyhow::Error {
+    CommandError::new(ErrorCode::InvalidRequest, message).into()
+}
+
+/// Build an `InvalidState` error
+pub fn invalid_state(message: impl Into<String>) -> anyhow::Error {
+    CommandError::new(ErrorCode::InvalidState, message).into()
 }
 
 #[derive(Debug)]
 pub enum Response {
     Success(String),
-    Error(String),
-    Data(String),
+    /// Success that changed nothing, e.g. adding an already sticky window
+    Unchanged(String),
+    /// Success that affected the listed windows
+    Applied {
+        message: String,
+        windows: Vec<u64>,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
+    Data(ResponseData),
+}
+
+impl Response {
+    /// Build an error response, keeping the code if the error carries one
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        let code = err
+            .downcast_ref::<CommandError>()
+            .map(|e| e.code)
+            .unwrap_or(ErrorCode::Internal);
+        Response::Error {
+            code,
+            message: err.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ResponseData {
+    Windows {
+        windows: Vec<u64>,
+    },
+    Handshake {
+        daemon_version: String,
+        protocol_version: u32,
+    },
+    FocusHistory {
+        history: Vec<FocusHistoryEntry>,
+    },
+    WindowList {
+        windows: Vec<WindowEntry>,
+    },
+    Status {
+        sticky: Vec<WindowEntry>,
+        staged: Vec<WindowEntry>,
+    },
+}
+
+/// nsticky's view of a window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowState {
+    Normal,
+    Sticky,
+    Staged,
+}
+
+impl std::fmt::Display for WindowState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            WindowState::Normal => "normal",
+            WindowState::Sticky => "sticky",
+            WindowState::Staged => "staged",
+        })
+    }
+}
+
+/// A window with its niri placement and nsticky state, as returned by list commands
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowEntry {
+    pub id: u64,
+    pub app_id: Option<String>,
+    pub title: Option<String>,
+    pub workspace_id: Option<u64>,
+    pub workspace_idx: Option<u64>,
+    pub workspace_name: Option<String>,
+    pub output: Option<String>,
+    pub is_floating: bool,
+    pub state: WindowState,
+    /// Seconds since the window became sticky or staged
+    pub since_secs: Option<u64>,
+}
+
+/// State change streamed to `watch` subscribers, one JSON object per line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Current sticky and staged windows, sent first and after missed events
+    Snapshot {
+        sticky: Vec<WindowEntry>,
+        staged: Vec<WindowEntry>,
+    },
+    /// A window became sticky
+    Sticky { id: u64 },
+    /// A window is no longer sticky
+    Unsticky { id: u64 },
+    /// A window was moved to the stage workspace
+    Staged { id: u64 },
+    /// A staged window was brought back, sticky again if it was sticky when staged
+    /// and a normal window if it was minimized as one
+    Unstaged { id: u64, sticky: bool },
+    /// A sticky or staged window was closed
+    Closed { id: u64 },
+    /// Sticky windows followed a workspace switch
+    FollowCompleted {
+        workspace_id: u64,
+        windows: Vec<u64>,
+    },
+    /// Something went wrong in the daemon outside of a request
+    Error { message: String },
+}
+
+/// One window in the daemon's focus history
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FocusHistoryEntry {
+    pub id: u64,
+    pub app_id: Option<String>,
+    pub title: Option<String>,
+    pub seconds_ago: u64,
+}
+
+/// Consume an optional match strategy flag preceding an app ID or title
+fn take_strategy<'a>(parts: &mut Peekable<impl Iterator<Item = &'a str>>) -> MatchStrategy {
+    match parts.peek().and_then(|flag| MatchStrategy::from_flag(flag)) {
+        Some(strategy) => {
+            parts.next();
+            strategy
+        }
+        None => MatchStrategy::default(),
+    }
+}
+
+/// The rest of the line as a title
+/// Encoded titles arrive as one argument; unquoted legacy titles are joined
+fn take_title<'a>(parts: &mut Peekable<impl Iterator<Item = &'a str>>) -> String {
+    parts.collect::<Vec<_>>().join(" ")
+}
+
+/// A window named by app ID or title on a `stage` or `unstage` line
+struct NamedWindow {
+    /// Given as `--toggle-appid` or `--toggle-title`
+    toggle: bool,
+    appid: Option<String>,
+    title: Option<String>,
+    strategy: MatchStrategy,
+}
+
+impl NamedWindow {
+    fn stage_args(self) -> StageArgs {
+        StageArgs {
+            appid: self.appid,
+            title: self.title,
+            strategy: self.strategy,
+            ..Default::default()
+        }
+    }
+}
+
+/// Parse `--appid`, `--title` or their `--toggle-` forms, an optional strategy
+/// flag and the app ID or title; `None` if the line starts with none of them
+fn parse_named_window<'a>(
+    parts: &mut Peekable<impl Iterator<Item = &'a str>>,
+    action: &str,
+) -> Result<Option<NamedWindow>> {
+    let (toggle, by_title) = match parts.peek().copied() {
+        Some("--appid") => (false, false),
+        Some("--title") => (false, true),
+        Some("--toggle-appid") => (true, false),
+        Some("--toggle-title") => (true, true),
+        _ => return Ok(None),
+    };
+    parts.next();
+    let action = if toggle { "toggle" } else { action };
+    let strategy = take_strategy(parts);
+    let named = if by_title {
+        let title = take_title(parts);
+        if title.is_empty() {
+            anyhow::bail!("Missing title for {action}");
+        }
+        NamedWindow {
+            toggle,
+            appid: None,
+            title: Some(title),
+            strategy,
+        }
+    } else {
+        let Some(appid) = parts.next() else {
+            anyhow::bail!("Missing appid for {action}");
+        };
+        NamedWindow {
+            toggle,
+            appid: Some(appid.to_string()),
+            title: None,
+            strategy,
+        }
+    };
+    Ok(Some(named))
+}
+
+/// Parse an optional strategy flag followed by a window id or selector
+/// Encoded selectors arrive as one argument; unquoted legacy selectors are joined
+fn parse_target<'a>(parts: &mut Peekable<impl Iterator<Item = &'a str>>) -> Result<Target> {
+    let strategy = take_strategy(parts);
+    let arg = parts.collect::<Vec<_>>().join(" ");
+    if arg.is_empty() {
+        Err(anyhow::anyhow!("Missing window id or selector"))
+    } else {
+        Ok(Target::from_arg(arg, strategy))
+    }
 }
 
 /// Parse string command to Request
 pub fn parse_request(line: &str) -> Result<Request> {
-    let line = line.trim();
-    let mut parts = line.split_whitespace();
+    let args = crate::wire::decode_args(line)?;
+    let mut parts = args.iter().map(String::as_str).peekable();
 
     match parts.next() {
-        Some("add") => {
-            if let Some(id_str) = parts.next() {
-                if let Ok(id) = id_str.parse::<u64>() {
-                    Ok(Request::Add { window_id: id })
-                } else {
-                    Err(anyhow::anyhow!("Invalid window id"))
-                }
-            } else {
-                Err(anyhow::anyhow!("Missing window id"))
-            }
-        }
-        Some("remove") => {
-            if let Some(id_str) = parts.next() {
-                if let Ok(id) = id_str.parse::<u64>() {
-                    Ok(Request::Remove { window_id: id })
-                } else {
-                    Err(anyhow::anyhow!("Invalid window id"))
-                }
-            } else {
-                Err(anyhow::anyhow!("Missing window id"))
-            }
-        }
+        Some("add") => Ok(Request::Add(parse_target(&mut parts)?)),
+        Some("remove") => Ok(Request::Remove(parse_target(&mut parts)?)),
+        Some("toggle") => Ok(Request::Toggle(parse_target(&mut parts)?)),
+        Some("handshake") => Ok(Request::Handshake),
+        Some("shutdown") => Ok(Request::Shutdown {
+            restore_staged: None,
+        }),
+        Some("focus_history") => Ok(Request::FocusHistory),
+        Some("watch") => Ok(Request::Watch),
+        Some("status") => Ok(Request::Status),
         Some("list") => Ok(Request::List),
+        Some("windows") => {
+            let selector = parts.collect::<Vec<_>>().join(" ");
+            Ok(Request::Windows {
+                selector: (!selector.is_empty()).then_some(selector),
+            })
+        }
         Some("toggle_active") => Ok(Request::ToggleActive),
         Some("toggle_appid") => {
+            let strategy = take_strategy(&mut parts);
             if let Some(appid) = parts.next() {
                 Ok(Request::ToggleAppid {
                     appid: appid.to_string(),
+                    strategy,
                 })
             } else {
                 Err(anyhow::anyhow!("Missing appid"))
             }
         }
         Some("toggle_title") => {
-            // 标题可能包含空格，所以我们将剩余部分连接起来
-            let title = parts.collect::<Vec<_>>().join(" ");
+            let strategy = take_strategy(&mut parts);
+            let title = take_title(&mut parts);
             if title.is_empty() {
                 Err(anyhow::anyhow!("Missing title"))
             } else {
-                Ok(Request::ToggleTitle { title })
+                Ok(Request::ToggleTitle { title, strategy })
             }
         }
         Some("stage") => {
-            let arg = parts.next();
-            if arg == Some("--toggle-appid") {
-                if let Some(appid) = parts.next() {
-                    let stage_args = StageArgs {
-                        appid: Some(appid.to_string()),
-                        ..Default::default()
-                    };
-                    return Ok(Request::Stage(stage_args));
-                } else {
-                    return Err(anyhow::anyhow!("Missing appid for toggle"));
-                }
-            } else if arg == Some("--toggle-title") {
-                // Title may contain spaces, join remaining parts
-                let title = parts.collect::<Vec<_>>().join(" ");
-                if title.is_empty() {
-                    return Err(anyhow::anyhow!("Missing title for toggle"));
-                } else {
-                    let stage_args = StageArgs {
-                        title: Some(title),
+            // Staging by app ID or title toggles, so both forms make the same request
+            if let Some(named) = parse_named_window(&mut parts, "stage")? {
+                return Ok(Request::Stage(named.stage_args()));
+            }
+            match parts.peek().copied() {
+                Some("--toggle") => {
+                    parts.next();
+                    let target = parse_target(&mut parts)?;
+                    Ok(Request::Stage(StageArgs {
+                        window_id: target.window_id,
+                        selector: target.selector,
+                        toggle: true,
+                        strategy: target.strategy,
                         ..Default::default()
-                    };
-                    return Ok(Request::Stage(stage_args));
+                    }))
                 }
-            }
-
-            match arg {
-                Some("--all") => Ok(Request::Stage(StageArgs {
-                    window_id: None,
+                // `--all` alone stages every sticky window; followed by a selector it is a strategy
+                Some("--all") if args.len() == 2 => Ok(Request::Stage(StageArgs {
                     all: true,
-                    list: false,
-                    active: false,
-                    appid: None,
-                    title: None,
+                    ..Default::default()
                 })),
                 Some("--list") => Ok(Request::Stage(StageArgs {
-                    window_id: None,
-                    all: false,
                     list: true,
-                    active: false,
-                    appid: None,
-                    title: None,
+                    ..Default::default()
                 })),
                 Some("--active") => Ok(Request::Stage(StageArgs {
-                    window_id: None,
-                    all: false,
-                    list: false,
                     active: true,
-                    appid: None,
-                    title: None,
+                    ..Default::default()
                 })),
-                Some("--appid") => {
-                    if let Some(appid) = parts.next() {
-                        Ok(Request::Stage(StageArgs {
-                            window_id: None,
-                            all: false,
-                            list: false,
-                            active: false,
-                            appid: Some(appid.to_string()),
-                            title: None,
-                        }))
-                    } else {
-                        Err(anyhow::anyhow!("Missing appid for stage"))
-                    }
-                }
-                Some("--title") => {
-                    // Title may contain spaces, join remaining parts
-                    let title = parts.collect::<Vec<_>>().join(" ");
-                    if title.is_empty() {
-                        Err(anyhow::anyhow!("Missing title for stage"))
-                    } else {
-                        Ok(Request::Stage(StageArgs {
-                            window_id: None,
-                            all: false,
-                            list: false,
-                            active: false,
-                            appid: None,
-                            title: Some(title),
-                        }))
-                    }
-                }
-                Some(id_str) => {
-                    if let Ok(id) = id_str.parse::<u64>() {
-                        Ok(Request::Stage(StageArgs {
-                            window_id: Some(id),
-                            all: false,
-                            list: false,
-                            active: false,
-                            appid: None,
-                            title: None,
-                        }))
-                    } else {
-                        Err(anyhow::anyhow!("Invalid window id"))
-                    }
+                Some(_) => {
+                    let target = parse_target(&mut parts)?;
+                    Ok(Request::Stage(StageArgs {
+                        window_id: target.window_id,
+                        selector: target.selector,
+                        strategy: target.strategy,
+                        ..Default::default()
+                    }))
                 }
                 None => Err(anyhow::anyhow!("Missing argument for stage")),
             }
         }
         Some("unstage") => {
-            let arg = parts.next();
-            if arg == Some("--toggle-appid") {
-                if let Some(appid) = parts.next() {
-                    return Ok(Request::ToggleAppid {
-                        appid: appid.to_string(),
-                    });
-                } else {
-                    return Err(anyhow::anyhow!("Missing appid for toggle"));
-                }
-            } else if arg == Some("--toggle-title") {
-                // Title may contain spaces, join remaining parts
-                let title = parts.collect::<Vec<_>>().join(" ");
-                if title.is_empty() {
-                    return Err(anyhow::anyhow!("Missing title for toggle"));
-                } else {
-                    return Ok(Request::ToggleTitle { title });
+            match parse_named_window(&mut parts, "unstage")? {
+                // Toggles are symmetric, so they share the stage toggle request
+                Some(named) if named.toggle => return Ok(Request::Stage(named.stage_args())),
+                Some(named) => {
+                    return Ok(Request::Unstage(UnstageArgs {
+                        appid: named.appid,
+                        title: named.title,
+                        strategy: named.strategy,
+                        ..Default::default()
+                    }));
                 }
+                None => {}
             }
-
-            match arg {
-                Some("--all") => Ok(Request::Unstage(UnstageArgs {
-                    window_id: None,
+            match parts.peek().copied() {
+                Some("--all") if args.len() == 2 => Ok(Request::Unstage(UnstageArgs {
                     all: true,
-                    active: false,
-                    appid: None,
-                    title: None,
+                    ..Default::default()
                 })),
                 Some("--active") => Ok(Request::Unstage(UnstageArgs {
-                    window_id: None,
-                    all: false,
                     active: true,
-                    appid: None,
-                    title: None,
+                    ..Default::default()
                 })),
-                Some("--appid") => {
-                    if let Some(appid) = parts.next() {
-                        Ok(Request::Unstage(UnstageArgs {
-                            window_id: None,
-                            all: false,
-                            active: false,
-                            appid: Some(appid.to_string()),
-                            title: None,
-                        }))
-                    } else {
-                        Err(anyhow::anyhow!("Missing appid for unstage"))
-                    }
-                }
-                Some("--title") => {
-                    // Title may contain spaces, join remaining parts
-                    let title = parts.collect::<Vec<_>>().join(" ");
-                    if title.is_empty() {
-                        Err(anyhow::anyhow!("Missing title for unstage"))
-                    } else {
-                        Ok(Request::Unstage(UnstageArgs {
-                            window_id: None,
-                            all: false,
-                            active: false,
-                            appid: None,
-                            title: Some(title),
-                        }))
-                    }
-                }
-                Some(id_str) => {
-                    if let Ok(id) = id_str.parse::<u64>() {
-                        Ok(Request::Unstage(UnstageArgs {
-                            window_id: Some(id),
-                            all: false,
-                            active: false,
-                            appid: None,
-                            title: None,
-                        }))
-                    } else {
-                        Err(anyhow::anyhow!("Invalid window id"))
-                    }
+                Some(_) => {
+                    let target = parse_target(&mut parts)?;
+                    Ok(Request::Unstage(UnstageArgs {
+                        window_id: target.window_id,
+                        selector: target.selector,
+                        strategy: target.strategy,
+                        ..Default::default()
+                    }))
                 }
                 None => Err(anyhow::anyhow!("Missing argument for unstage")),
             }
@@ -269,11 +594,183 @@ pub fn parse_request(line: &str) -> Result<Request> {
     }
 }
 
+/// Parse a JSON request line, checking the protocol version
+pub fn parse_json_request(line: &str) -> Result<Request, CommandError> {
+    let envelope: JsonRequest = serde_json::from_str(line.trim())
+        .map_err(|e| CommandError::new(ErrorCode::InvalidRequest, e.to_string()))?;
+    if envelope.version > PROTOCOL_VERSION {
+        return Err(CommandError::new(
+            ErrorCode::UnsupportedVersion,
+            format!(
+                "Protocol version {} is not supported (daemon speaks {})",
+                envelope.version, PROTOCOL_VERSION
+            ),
+        ));
+    }
+    Ok(envelope.request)
+}
+
 /// Convert Response to string
 pub fn format_response(response: Response) -> String {
     match response {
-        Response::Success(msg) => msg,
-        Response::Error(msg) => format!("Error: {msg}"),
-        Response::Data(data) => data,
+        Response::Success(msg)
+        | Response::Unchanged(msg)
+        | Response::Applied { message: msg, .. } => format!("{msg}\n"),
+        Response::Error { message, .. } => format!("Error: {message}\n"),
+        Response::Data(ResponseData::Windows { windows }) => format!("{windows:?}\n"),
+        Response::Data(ResponseData::Handshake {
+            daemon_version,
+            protocol_version,
+        }) => format!("nsticky {daemon_version} (protocol {protocol_version})\n"),
+        // Text clients keep getting the bare id list they always did
+        Response::Data(ResponseData::WindowList { windows }) => {
+            let ids: Vec<u64> = windows.iter().map(|w| w.id).collect();
+            format!("{ids:?}\n")
+        }
+        Response::Data(ResponseData::Status { sticky, staged }) => {
+            let ids = |windows: &[WindowEntry]| windows.iter().map(|w| w.id).collect::<Vec<_>>();
+            format!("sticky {:?} staged {:?}\n", ids(&sticky), ids(&staged))
+        }
+        Response::Data(ResponseData::FocusHistory { history }) => {
+            if history.is_empty() {
+                return "No focus history yet\n".to_string();
+            }
+            history
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| {
+                    format!(
+                        "{:>2}. {:<8} {:<24} {} ({}s ago)\n",
+                        i + 1,
+                        entry.id,
+                        entry.app_id.as_deref().unwrap_or("-"),
+                        entry.title.as_deref().unwrap_or("-"),
+                        entry.seconds_ago
+                    )
+                })
+                .collect()
+        }
+    }
+}
+
+/// JSON response line as decoded by clients
+#[derive(Debug, Deserialize)]
+pub struct JsonResponse {
+    #[serde(default)]
+    pub message: Option<String>,
+    /// `Some(false)` when the command succeeded without changing anything
+    #[serde(default)]
+    pub changed: Option<bool>,
+    #[serde(default)]
+    pub data: Option<serde_json::Value>,
+    #[serde(default)]
+    pub error: Option<JsonError>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JsonError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+/// Convert Response to a single JSON line
+pub fn format_json_response(response: Response) -> String {
+    let value = match response {
+        Response::Success(msg) => json!({ "ok": true, "message": msg }),
+        Response::Unchanged(msg) => json!({ "ok": true, "changed": false, "message": msg }),
+        Response::Applied { message, windows } => json!({
+            "ok": true,
+            "message": message,
+            "data": { "windows": windows },
+        }),
+        Response::Error { code, message } => json!({
+            "ok": false,
+            "error": { "code": code, "message": message },
+        }),
+        Response::Data(data) => json!({ "ok": true, "data": data }),
+    };
+    format!("{value}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire::encode_args;
+
+    #[test]
+    fn encoded_titles_parse_verbatim() {
+        let title = "  \"Inbox\"\t—  Mail  ";
+        match parse_request(&encode_args(&["toggle_title", title])).unwrap() {
+            Request::ToggleTitle { title: parsed, .. } => assert_eq!(parsed, title),
+            other => panic!("unexpected request: {other:?}"),
+        }
+        match parse_request(&encode_args(&["stage", "--toggle-title", title])).unwrap() {
+            Request::Stage(args) => assert_eq!(args.title.as_deref(), Some(title)),
+            other => panic!("unexpected request: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn numbers_are_ids_and_anything_else_a_selector() {
+        match parse_request("add 42").unwrap() {
+            Request::Add(target) => assert_eq!(target.window_id, Some(42)),
+            other => panic!("unexpected request: {other:?}"),
+        }
+        match parse_request("stage --all app:firefox").unwrap() {
+            Request::Stage(args) => {
+                assert!(!args.all);
+                assert_eq!(args.selector.as_deref(), Some("app:firefox"));
+                assert_eq!(args.strategy, MatchStrategy::All);
+            }
+            other => panic!("unexpected request: {other:?}"),
+        }
+        match parse_request("stage --all").unwrap() {
+            Request::Stage(args) => assert!(args.all && args.selector.is_none()),
+            other => panic!("unexpected request: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn legacy_unquoted_titles_are_joined() {
+        match parse_request("toggle_title Mozilla Firefox").unwrap() {
+            Request::ToggleTitle { title, .. } => assert_eq!(title, "Mozilla Firefox"),
+            other => panic!("unexpected request: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn stage_and_unstage_name_windows_alike() {
+        match parse_request("unstage --title --all Mozilla Firefox").unwrap() {
+            Request::Unstage(args) => {
+                assert_eq!(args.title.as_deref(), Some("Mozilla Firefox"));
+                assert_eq!(args.strategy, MatchStrategy::All);
+            }
+            other => panic!("unexpected request: {other:?}"),
+        }
+        for line in ["stage --appid foot", "unstage --toggle-appid foot"] {
+            match parse_request(line).unwrap() {
+                Request::Stage(args) => assert_eq!(args.appid.as_deref(), Some("foot")),
+                other => panic!("unexpected request: {other:?}"),
+            }
+        }
+        let err = parse_request("unstage --title --first").unwrap_err();
+        assert_eq!(err.to_string(), "Missing title for unstage");
+        let err = parse_request("stage --toggle-appid").unwrap_err();
+        assert_eq!(err.to_string(), "Missing appid for toggle");
+    }
+
+    #[test]
+    fn events_are_tagged_objects() {
+        let event = Event::FollowCompleted {
+            workspace_id: 2,
+            windows: vec![10, 12],
+        };
+        let line = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            line,
+            r#"{"event":"follow_completed","workspace_id":2,"windows":[10,12]}"#
+        );
+        let parsed: Event = serde_json::from_str(r#"{"event":"staged","id":7}"#).unwrap();
+        assert!(matches!(parsed, Event::Staged { id: 7 }));
     }
 }
@@ -0,0 +1,169 @@
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::system_integration::WorkspaceInfo;
+
+/// A workspace in a follow scope: an index (`3`), an index range (`1-4`) or a
+/// name glob (`dev*`, `games`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum WorkspacePattern {
+    Indexes { from: u64, to: u64 },
+    Name { glob: String, regex: Regex },
+}
+
+impl WorkspacePattern {
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+        if input.is_empty() {
+            anyhow::bail!("Empty workspace pattern");
+        }
+        if let Ok(idx) = input.parse::<u64>() {
+            return Ok(WorkspacePattern::Indexes { from: idx, to: idx });
+        }
+        // Accept the en dash people copy from prose, as in "1–4"
+        if let Some((from, to)) = input.split_once(['-', '–'])
+            && let (Ok(from), Ok(to)) = (from.trim().parse::<u64>(), to.trim().parse::<u64>())
+        {
+            if from > to {
+                anyhow::bail!("Workspace range '{input}' runs backwards");
+            }
+            return Ok(WorkspacePattern::Indexes { from, to });
+        }
+
+        let mut regex = String::from("^");
+        for c in input.chars() {
+            match c {
+                '*' => regex.push_str(".*"),
+                '?' => regex.push('.'),
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+        Ok(WorkspacePattern::Name {
+            glob: input.to_string(),
+            regex: Regex::new(&regex)?,
+        })
+    }
+
+    pub fn matches(&self, workspace: &WorkspaceInfo) -> bool {
+        match self {
+            WorkspacePattern::Indexes { from, to } => (*from..=*to).contains(&workspace.idx),
+            WorkspacePattern::Name { regex, .. } => workspace
+                .name
+                .as_deref()
+                .is_some_and(|name| regex.is_match(name)),
+        }
+    }
+}
+
+impl std::fmt::Display for WorkspacePattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorkspacePattern::Indexes { from, to } if from == to => write!(f, "{from}"),
+            WorkspacePattern::Indexes { from, to } => write!(f, "{from}-{to}"),
+            WorkspacePattern::Name { glob, .. } => f.write_str(glob),
+        }
+    }
+}
+
+impl TryFrom<String> for WorkspacePattern {
+    type Error = anyhow::Error;
+
+    fn try_from(input: String) -> Result<Self> {
+        Self::parse(&input)
+    }
+}
+
+impl From<WorkspacePattern> for String {
+    fn from(pattern: WorkspacePattern) -> Self {
+        pattern.to_string()
+    }
+}
+
+/// Workspaces a sticky window follows onto
+///
+/// On any other workspace the window stays where it is and rejoins on the next
+/// allowed one.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FollowScope {
+    /// Follow only onto these; empty means every workspace
+    pub only: Vec<WorkspacePattern>,
+    /// Never follow onto these, even if listed in `only`
+    pub never: Vec<WorkspacePattern>,
+}
+
+impl FollowScope {
+    pub fn parse(only: &[String], never: &[String]) -> Result<Self> {
+        let parse_all = |patterns: &[String]| -> Result<Vec<WorkspacePattern>> {
+            patterns
+                .iter()
+                .map(|p| WorkspacePattern::parse(p))
+                .collect()
+        };
+        Ok(FollowScope {
+            only: parse_all(only)?,
+            never: parse_all(never)?,
+        })
+    }
+
+    /// Whether the window may follow onto this workspace
+    pub fn allows(&self, workspace: &WorkspaceInfo) -> bool {
+        (self.only.is_empty() || self.only.iter().any(|p| p.matches(workspace)))
+            && !self.never.iter().any(|p| p.matches(workspace))
+    }
+
+    pub fn is_unrestricted(&self) -> bool {
+        self.only.is_empty() && self.never.is_empty()
+    }
+}
+
+impl std::fmt::Display for FollowScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |patterns: &[WorkspacePattern]| {
+            patterns
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match (self.only.is_empty(), self.never.is_empty()) {
+            (true, true) => f.write_str("every workspace"),
+            (false, true) => write!(f, "only {}", list(&self.only)),
+            (true, false) => write!(f, "every workspace except {}", list(&self.never)),
+            (false, false) => write!(f, "only {}, except {}", list(&self.only), list(&self.never)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspace(idx: u64, name: Option<&str>) -> WorkspaceInfo {
+        WorkspaceInfo {
+            id: idx + 100,
+            idx,
+            name: name.map(String::from),
+            output: None,
+        }
+    }
+
+    #[test]
+    fn scope_combines_only_and_never() {
+        let scope = FollowScope::parse(
+            &["1–4".to_string(), "dev*".to_string()],
+            &["games".to_string(), "3".to_string()],
+        )
+        .unwrap();
+        assert!(scope.allows(&workspace(1, None)));
+        assert!(!scope.allows(&workspace(3, None)));
+        assert!(!scope.allows(&workspace(5, None)));
+        assert!(scope.allows(&workspace(7, Some("dev-rust"))));
+        assert!(!scope.allows(&workspace(2, Some("games"))));
+        assert_eq!(scope.to_string(), "only 1-4, dev*, except games, 3");
+        assert!(WorkspacePattern::parse("4-1").is_err());
+    }
+}
@@ -0,0 +1,371 @@
+use anyhow::Result;
+use regex::Regex;
+
+use crate::system_integration::{WindowInfo, WorkspaceInfo};
+
+/// Window selector, evaluated daemon-side against live window data
+///
+/// Syntax (terms combine with `and`, `or`, `not` and parentheses):
+/// - `42` or `id:42` - window id
+/// - `app:firefox` / `app~/^org\.gnome\./` - exact app ID / app ID regex
+/// - `title:Inbox` / `title~/- Mail$/` - title substring / title regex
+/// - `pid:1234` - process id
+/// - `workspace:3` / `workspace:dev` - workspace index or name
+/// - `focused`, `floating`, `tiled`
+///
+/// Values containing spaces can be double-quoted: `title:"Mozilla Firefox"`.
+#[derive(Debug, Clone)]
+pub enum Selector {
+    Id(u64),
+    App(String),
+    AppRegex(Regex),
+    Title(String),
+    TitleRegex(Regex),
+    Pid(i64),
+    Workspace(String),
+    Focused,
+    Floating,
+    Tiled,
+    And(Box<Selector>, Box<Selector>),
+    Or(Box<Selector>, Box<Selector>),
+    Not(Box<Selector>),
+}
+
+impl Selector {
+    /// Whether the window matches this selector
+    pub fn matches(&self, window: &WindowInfo, workspaces: &[WorkspaceInfo]) -> bool {
+        match self {
+            Selector::Id(id) => window.id == *id,
+            Selector::App(app) => window.app_id.as_deref() == Some(app),
+            Selector::AppRegex(re) => window.app_id.as_deref().is_some_and(|a| re.is_match(a)),
+            Selector::Title(title) => window.title.as_deref().is_some_and(|t| t.contains(title)),
+            Selector::TitleRegex(re) => window.title.as_deref().is_some_and(|t| re.is_match(t)),
+            Selector::Pid(pid) => window.pid == Some(*pid),
+            Selector::Workspace(reference) => window.workspace_id.is_some_and(|ws_id| {
+                workspaces
+                    .iter()
+                    .any(|ws| ws.id == ws_id && ws.matches_reference(reference))
+            }),
+            Selector::Focused => window.is_focused,
+            Selector::Floating => window.is_floating,
+            Selector::Tiled => !window.is_floating,
+            Selector::And(a, b) => a.matches(window, workspaces) && b.matches(window, workspaces),
+            Selector::Or(a, b) => a.matches(window, workspaces) || b.matches(window, workspaces),
+            Selector::Not(a) => !a.matches(window, workspaces),
+        }
+    }
+
+    /// Whether evaluating this selector needs the workspace list
+    pub fn uses_workspaces(&self) -> bool {
+        match self {
+            Selector::Workspace(_) => true,
+            Selector::And(a, b) | Selector::Or(a, b) => a.uses_workspaces() || b.uses_workspaces(),
+            Selector::Not(a) => a.uses_workspaces(),
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Selector::Id(id) => write!(f, "id:{}", id),
+            Selector::App(app) => write!(f, "app:{}", quote(app)),
+            Selector::AppRegex(re) => write!(f, "app~/{}/", re.as_str().replace('/', "\\/")),
+            Selector::Title(title) => write!(f, "title:{}", quote(title)),
+            Selector::TitleRegex(re) => write!(f, "title~/{}/", re.as_str().replace('/', "\\/")),
+            Selector::Pid(pid) => write!(f, "pid:{}", pid),
+            Selector::Workspace(reference) => write!(f, "workspace:{}", quote(reference)),
+            Selector::Focused => f.write_str("focused"),
+            Selector::Floating => f.write_str("floating"),
+            Selector::Tiled => f.write_str("tiled"),
+            Selector::And(a, b) => write!(f, "({} and {})", a, b),
+            Selector::Or(a, b) => write!(f, "({} or {})", a, b),
+            Selector::Not(a) => write!(f, "not {}", a),
+        }
+    }
+}
+
+fn quote(value: &str) -> String {
+    if value.is_empty()
+        || value.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '(' | ')'))
+    {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Word(String),
+}
+
+/// Split a selector into words and parentheses
+/// Double quotes group whitespace, and `~/.../` regex bodies are taken verbatim up to the closing `/`
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c == '(' || c == ')' {
+            chars.next();
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            continue;
+        }
+
+        let mut word = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || c == '(' || c == ')' {
+                break;
+            }
+            chars.next();
+            match c {
+                '"' => loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => word.push(escaped),
+                            None => anyhow::bail!("Unterminated escape in selector"),
+                        },
+                        Some(c) => word.push(c),
+                        None => anyhow::bail!("Unterminated quote in selector"),
+                    }
+                },
+                '~' if chars.peek() == Some(&'/') => {
+                    chars.next();
+                    word.push_str("~/");
+                    loop {
+                        match chars.next() {
+                            Some('/') => break,
+                            Some('\\') if chars.peek() == Some(&'/') => {
+                                chars.next();
+                                word.push('/');
+                            }
+                            Some(c) => word.push(c),
+                            None => anyhow::bail!("Unterminated regex in selector"),
+                        }
+                    }
+                    word.push('/');
+                }
+                _ => word.push(c),
+            }
+        }
+        tokens.push(Token::Word(word));
+    }
+
+    Ok(tokens)
+}
+
+/// Parse a selector expression
+pub fn parse(input: &str) -> Result<Selector> {
+    let tokens = tokenize(input)?;
+    if tokens.is_empty() {
+        anyhow::bail!("Empty selector");
+    }
+    let mut parser = Parser { tokens, pos: 0 };
+    let selector = parser.parse_or()?;
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        anyhow::bail!("Unexpected {:?} in selector", token);
+    }
+    Ok(selector)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_word(&self, word: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some(Token::Word(w)) if w == word)
+    }
+
+    fn parse_or(&mut self) -> Result<Selector> {
+        let mut left = self.parse_and()?;
+        while self.peek_word("or") {
+            self.pos += 1;
+            let right = self.parse_and()?;
+            left = Selector::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Selector> {
+        let mut left = self.parse_unary()?;
+        while self.peek_word("and") {
+            self.pos += 1;
+            let right = self.parse_unary()?;
+            left = Selector::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Selector> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        match token {
+            Some(Token::Word(w)) if w == "not" => Ok(Selector::Not(Box::new(self.parse_unary()?))),
+            Some(Token::Open) => {
+                let inner = self.parse_or()?;
+                match self.tokens.get(self.pos) {
+                    Some(Token::Close) => {
+                        self.pos += 1;
+                        Ok(inner)
+                    }
+                    _ => anyhow::bail!("Missing ')' in selector"),
+                }
+            }
+            Some(Token::Word(w)) => parse_term(w),
+            Some(Token::Close) => anyhow::bail!("Unexpected ')' in selector"),
+            None => anyhow::bail!("Selector ends unexpectedly"),
+        }
+    }
+}
+
+fn parse_term(term: &str) -> Result<Selector> {
+    if let Ok(id) = term.parse::<u64>() {
+        return Ok(Selector::Id(id));
+    }
+    match term {
+        "focused" => return Ok(Selector::Focused),
+        "floating" => return Ok(Selector::Floating),
+        "tiled" => return Ok(Selector::Tiled),
+        "and" | "or" => anyhow::bail!("Missing operand before '{}'", term),
+        _ => {}
+    }
+
+    if let Some((key, pattern)) = term.split_once("~/")
+        && !key.contains(':')
+    {
+        let pattern = pattern
+            .strip_suffix('/')
+            .ok_or_else(|| anyhow::anyhow!("Unterminated regex in '{}'", term))?;
+        let re = Regex::new(pattern)
+            .map_err(|e| anyhow::anyhow!("Invalid regex in '{}': {}", term, e))?;
+        return match key {
+            "app" => Ok(Selector::AppRegex(re)),
+            "title" => Ok(Selector::TitleRegex(re)),
+            _ => anyhow::bail!("Unknown regex selector '{}'", key),
+        };
+    }
+
+    let Some((key, value)) = term.split_once(':') else {
+        anyhow::bail!("Unknown selector '{}'", term);
+    };
+    match key {
+        "id" => value
+            .parse()
+            .map(Selector::Id)
+            .map_err(|_| anyhow::anyhow!("Invalid window id '{}'", value)),
+        "app" => Ok(Selector::App(value.to_string())),
+        "title" => Ok(Selector::Title(value.to_string())),
+        "pid" => value
+            .parse()
+            .map(Selector::Pid)
+            .map_err(|_| anyhow::anyhow!("Invalid pid '{}'", value)),
+        "workspace" | "ws" => Ok(Selector::Workspace(value.to_string())),
+        _ => anyhow::bail!("Unknown selector '{}'", key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(id: u64, app_id: &str, title: &str) -> WindowInfo {
+        WindowInfo {
+            id,
+            app_id: Some(app_id.to_string()),
+            title: Some(title.to_string()),
+            pid: Some(1000 + id as i64),
+            workspace_id: Some(1),
+            is_focused: false,
+            is_floating: false,
+            focus_timestamp: None,
+            layout: None,
+        }
+    }
+
+    fn workspaces() -> Vec<WorkspaceInfo> {
+        vec![WorkspaceInfo {
+            id: 1,
+            idx: 3,
+            name: Some("dev".to_string()),
+            output: Some("DP-1".to_string()),
+        }]
+    }
+
+    fn matches(selector: &str, window: &WindowInfo) -> bool {
+        parse(selector).unwrap().matches(window, &workspaces())
+    }
+
+    #[test]
+    fn simple_terms() {
+        let mut w = window(42, "firefox", "Mozilla  Firefox");
+        assert!(matches("42", &w));
+        assert!(matches("id:42", &w));
+        assert!(matches("app:firefox", &w));
+        assert!(!matches("app:fire", &w));
+        assert!(matches("title:\"Mozilla  Firefox\"", &w));
+        assert!(matches("pid:1042", &w));
+        assert!(matches("workspace:3", &w));
+        assert!(matches("workspace:dev", &w));
+        assert!(!matches("workspace:1", &w));
+        assert!(matches("tiled", &w));
+        w.is_floating = true;
+        w.is_focused = true;
+        assert!(matches("floating and focused", &w));
+    }
+
+    #[test]
+    fn regex_terms_keep_spaces_and_slashes() {
+        let w = window(1, "org.gnome.Nautilus", "docs / Home Folder");
+        assert!(matches(r"app~/^org\.gnome\./", &w));
+        assert!(matches(r"title~/docs \/ Home/", &w));
+        assert!(!matches("title~/^Home/", &w));
+        assert!(parse("title~/unterminated").is_err());
+        assert!(parse("title~/(/").is_err());
+    }
+
+    #[test]
+    fn boolean_combinations() {
+        let w = window(7, "kitty", "zsh");
+        assert!(matches("app:kitty and not floating", &w));
+        assert!(matches("app:firefox or app:kitty", &w));
+        assert!(!matches("not (app:firefox or app:kitty)", &w));
+        assert!(matches("app:firefox or app:kitty and title:zsh", &w));
+    }
+
+    #[test]
+    fn malformed_selectors_are_rejected() {
+        for bad in [
+            "",
+            "bogus",
+            "color:red",
+            "id:x",
+            "app:a and",
+            "(app:a",
+            "app:a )",
+            "and",
+        ] {
+            assert!(parse(bad).is_err(), "{bad:?} should not parse");
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let input = r#"(app:firefox or title:"two  words") and not workspace:dev"#;
+        let selector = parse(input).unwrap();
+        assert_eq!(
+            parse(&selector.to_string()).unwrap().to_string(),
+            selector.to_string()
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::scope::FollowScope;
+
+/// Daemon state saved on shutdown and restored on the next start
+///
+/// Window ids are only meaningful within one niri session and niri reuses
+/// them, so the file records the session's IPC socket and is ignored by a
+/// daemon running in another one. Windows that no longer exist are dropped
+/// when it is loaded.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DaemonState {
+    /// `NIRI_SOCKET` of the niri session the window ids belong to
+    pub niri_socket: Option<PathBuf>,
+    pub sticky: Vec<SavedWindow>,
+    pub staged: Vec<SavedWindow>,
+    /// Follow scopes set from the CLI, by window id
+    pub follow_scopes: HashMap<u64, FollowScope>,
+    /// Where staged windows were staged from, by window id
+    pub origins: HashMap<u64, WindowOrigin>,
+    /// Staged windows that were normal, not sticky, before staging
+    pub staged_normal: HashSet<u64>,
+}
+
+/// A tracked window and when it joined its set
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SavedWindow {
+    pub id: u64,
+    /// Unix time in seconds
+    pub since: u64,
+}
+
+/// Where a window was before it was staged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowOrigin {
+    pub workspace_id: u64,
+    pub workspace_idx: u64,
+    pub workspace_name: Option<String>,
+    pub output: Option<String>,
+    /// 1-based column in the scrolling layout, for tiled windows
+    pub column: Option<u64>,
+    /// Whether the window was floating, even if its geometry is unknown
+    #[serde(default)]
+    pub was_floating: bool,
+    /// Position and size, for floating windows whose layout niri reported
+    pub floating: Option<FloatingGeometry>,
+}
+
+/// Position and size of a floating window, in logical pixels
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FloatingGeometry {
+    pub x: f64,
+    pub y: f64,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// The niri session this process runs in, identified by its IPC socket
+pub fn niri_session() -> Option<PathBuf> {
+    std::env::var_os("NIRI_SOCKET").map(PathBuf::from)
+}
+
+/// State file belonging to a control socket, e.g. `nsticky.sock` -> `nsticky.state.json`
+pub fn path_for(socket_path: &Path) -> PathBuf {
+    socket_path.with_extension("state.json")
+}
+
+/// Load saved state, `None` if there is none
+pub fn load(path: &Path) -> Result<Option<DaemonState>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let state = serde_json::from_str(&text)
+        .with_context(|| format!("Invalid state file {}", path.display()))?;
+    Ok(Some(state))
+}
+
+/// Save state atomically, readable only by the user
+pub fn save(path: &Path, state: &DaemonState) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    file.write_all(serde_json::to_string_pretty(state)?.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))
+}
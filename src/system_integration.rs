@@ -1,33 +1,106 @@
 use anyhow::Result;
 use serde_json::{Value, json};
 use std::collections::HashSet;
+use std::time::Duration;
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     net::UnixStream,
     process::Command,
 };
 
+use crate::selector::Selector;
+use crate::state::FloatingGeometry;
+
 /// Window information structure
 #[derive(Debug, Clone)]
 pub struct WindowInfo {
     pub id: u64,
     pub app_id: Option<String>,
     pub title: Option<String>,
+    pub pid: Option<i64>,
+    pub workspace_id: Option<u64>,
+    pub is_focused: bool,
+    pub is_floating: bool,
+    /// When niri last focused the window, if it reports it
+    pub focus_timestamp: Option<Duration>,
+    /// Position and size, if niri reports them
+    pub layout: Option<WindowLayout>,
 }
 
-/// Get active workspace ID from Niri
-pub async fn get_active_workspace_id() -> Result<u64> {
-    let output = tokio::process::Command::new("niri")
-        .args(["msg", "-j", "workspaces"])
+/// Where a window sits in its workspace
+#[derive(Debug, Clone, Copy)]
+pub struct WindowLayout {
+    /// 1-based column index in the scrolling layout, `None` when floating
+    pub column: Option<u64>,
+    /// Position in the workspace view, set for floating windows
+    pub position: Option<(f64, f64)>,
+    /// Window size in logical pixels
+    pub size: (i32, i32),
+}
+
+/// Workspace information structure
+#[derive(Debug, Clone)]
+pub struct WorkspaceInfo {
+    pub id: u64,
+    pub idx: u64,
+    pub name: Option<String>,
+    pub output: Option<String>,
+}
+
+impl WorkspaceInfo {
+    /// Whether a user-facing reference (index or name) points at this workspace
+    pub fn matches_reference(&self, reference: &str) -> bool {
+        match reference.parse::<u64>() {
+            Ok(idx) => self.idx == idx,
+            Err(_) => self.name.as_deref() == Some(reference),
+        }
+    }
+}
+
+/// Run `niri msg --json <query>` and parse its output
+async fn niri_query(query: &str) -> Result<Value> {
+    tracing::debug!(query, "Querying niri");
+    let output = Command::new("niri")
+        .args(["msg", "--json", query])
         .output()
         .await?;
-
     if !output.status.success() {
-        anyhow::bail!("Failed to get workspaces");
+        anyhow::bail!(
+            "niri msg {query} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Get all workspaces from Niri
+pub async fn get_workspaces() -> Result<Vec<WorkspaceInfo>> {
+    let json = niri_query("workspaces").await?;
+    let mut workspaces = Vec::new();
+    if let Some(arr) = json.as_array() {
+        for item in arr {
+            if let Some(id) = item.get("id").and_then(|v| v.as_u64()) {
+                workspaces.push(WorkspaceInfo {
+                    id,
+                    idx: item.get("idx").and_then(|v| v.as_u64()).unwrap_or(0),
+                    name: item
+                        .get("name")
+                        .and_then(|v| v.as_str())
+                        .map(|s| s.to_string()),
+                    output: item
+                        .get("output")
+                        .and_then(|v| v.as_str())
+                        .map(|s| s.to_string()),
+                });
+            }
+        }
     }
+    Ok(workspaces)
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let json: serde_json::Value = serde_json::from_str(&stdout)?;
+/// Get active workspace ID from Niri
+pub async fn get_active_workspace_id() -> Result<u64> {
+    let json = niri_query("workspaces").await?;
 
     if let Some(workspaces) = json.as_array() {
         for workspace in workspaces {
@@ -44,15 +117,7 @@ pub async fn get_active_workspace_id() -> Result<u64> {
 
 /// Get active window ID from Niri
 pub async fn get_active_window_id() -> Result<u64> {
-    let output = tokio::process::Command::new("niri")
-        .args(["msg", "--json", "focused-window"])
-        .output()
-        .await?;
-    if !output.status.success() {
-        anyhow::bail!("Failed to get focused window");
-    }
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let json: serde_json::Value = serde_json::from_str(&stdout)?;
+    let json = niri_query("focused-window").await?;
     if let Some(id) = json.get("id").and_then(|v| v.as_u64()) {
         Ok(id)
     } else {
@@ -61,16 +126,8 @@ pub async fn get_active_window_id() -> Result<u64> {
 }
 
 /// Get full window information from Niri
-async fn get_full_window_info() -> Result<Vec<WindowInfo>> {
-    let output = Command::new("niri")
-        .args(["msg", "--json", "windows"])
-        .output()
-        .await?;
-    if !output.status.success() {
-        anyhow::bail!("Failed to get windows list");
-    }
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let json: Value = serde_json::from_str(&stdout)?;
+pub async fn get_full_window_info() -> Result<Vec<WindowInfo>> {
+    let json = niri_query("windows").await?;
     let mut windows = Vec::new();
     if let Some(arr) = json.as_array() {
         for item in arr {
@@ -83,7 +140,45 @@ async fn get_full_window_info() -> Result<Vec<WindowInfo>> {
                     .get("title")
                     .and_then(|v| v.as_str())
                     .map(|s| s.to_string());
-                windows.push(WindowInfo { id, app_id, title });
+                let pid = item.get("pid").and_then(|v| v.as_i64());
+                let workspace_id = item.get("workspace_id").and_then(|v| v.as_u64());
+                let is_floating = item
+                    .get("is_floating")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                let is_focused = item
+                    .get("is_focused")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                let focus_timestamp = item.get("focus_timestamp").and_then(|ts| {
+                    let secs = ts.get("secs")?.as_u64()?;
+                    let nanos = ts.get("nanos")?.as_u64()?;
+                    Some(Duration::new(secs, nanos as u32))
+                });
+                let layout = item.get("layout").map(|layout| {
+                    let pair = |key: &str| {
+                        let pair = layout.get(key)?.as_array()?;
+                        Some((pair.first()?.as_f64()?, pair.get(1)?.as_f64()?))
+                    };
+                    WindowLayout {
+                        column: pair("pos_in_scrolling_layout").map(|(col, _)| col as u64),
+                        position: pair("tile_pos_in_workspace_view"),
+                        size: pair("window_size")
+                            .map(|(w, h)| (w as i32, h as i32))
+                            .unwrap_or_default(),
+                    }
+                });
+                windows.push(WindowInfo {
+                    id,
+                    app_id,
+                    title,
+                    pid,
+                    workspace_id,
+                    is_focused,
+                    is_floating,
+                    focus_timestamp,
+                    layout,
+                });
             }
         }
     }
@@ -96,80 +191,105 @@ pub async fn get_full_window_list() -> Result<HashSet<u64>> {
     Ok(windows.into_iter().map(|w| w.id).collect())
 }
 
-/// Find window by application ID
-pub async fn find_window_by_appid(appid: &str) -> Result<Option<u64>> {
+/// Find all windows matching the selector, in niri's list order
+pub async fn find_windows(selector: &Selector) -> Result<Vec<WindowInfo>> {
     let windows = get_full_window_info().await?;
-    for window in windows {
-        if let Some(window_appid) = window.app_id
-            && window_appid == appid
-        {
-            return Ok(Some(window.id));
-        }
-    }
-    Ok(None)
-}
-
-/// Find window by title
-pub async fn find_window_by_title(title: &str) -> Result<Option<u64>> {
-    let windows = get_full_window_info().await?;
-    for window in windows {
-        if let Some(window_title) = window.title
-            && window_title.contains(title)
-        {
-            return Ok(Some(window.id));
-        }
-    }
-    Ok(None)
+    let workspaces = if selector.uses_workspaces() {
+        get_workspaces().await?
+    } else {
+        Vec::new()
+    };
+    Ok(windows
+        .into_iter()
+        .filter(|w| selector.matches(w, &workspaces))
+        .collect())
 }
 
-/// Move window to workspace
-pub async fn move_to_workspace(win_id: u64, ws_id: u64) -> Result<()> {
+/// Run a niri action over the IPC socket
+pub async fn niri_action(action: Value) -> Result<()> {
     let socket_path = std::env::var("NIRI_SOCKET")?;
-
     let stream = UnixStream::connect(&socket_path).await?;
     let (reader, mut writer) = stream.into_split();
     let mut reader = BufReader::new(reader);
 
-    let cmd = json!({
-        "Action": {
-            "MoveWindowToWorkspace": {
-                "window_id": win_id,
-                "focus": false,
-                "reference": { "Id": ws_id }
-            }
-        }
-    });
-    let cmd_str = serde_json::to_string(&cmd)? + "\n";
-
+    let cmd_str = serde_json::to_string(&json!({ "Action": action }))? + "\n";
     writer.write_all(cmd_str.as_bytes()).await?;
     writer.flush().await?;
 
     let mut response = String::new();
     reader.read_line(&mut response).await?;
-    println!("move_to_workspace response: {}", response.trim());
+    tracing::debug!(%action, response = response.trim(), "Ran niri action");
+    if let Ok(Value::Object(reply)) = serde_json::from_str::<Value>(&response)
+        && let Some(err) = reply.get("Err")
+    {
+        anyhow::bail!(
+            "niri refused the action: {}",
+            err.as_str().unwrap_or_default()
+        );
+    }
     Ok(())
 }
 
+/// Move window to workspace
+pub async fn move_to_workspace(win_id: u64, ws_id: u64) -> Result<()> {
+    niri_action(json!({
+        "MoveWindowToWorkspace": {
+            "window_id": win_id,
+            "focus": false,
+            "reference": { "Id": ws_id }
+        }
+    }))
+    .await
+}
+
 /// Move window to named workspace
 pub async fn move_to_named_workspace(win_id: u64, workspace_name: &str) -> Result<()> {
-    let socket_path = std::env::var("NIRI_SOCKET")?;
-    let stream = UnixStream::connect(&socket_path).await?;
-    let (reader, mut writer) = stream.into_split();
-    let mut reader = BufReader::new(reader);
-    let cmd = json!({
-        "Action": {
-            "MoveWindowToWorkspace": {
-                "window_id": win_id,
-                "focus": false,
-                "reference": { "Name": workspace_name }
-            }
+    niri_action(json!({
+        "MoveWindowToWorkspace": {
+            "window_id": win_id,
+            "focus": false,
+            "reference": { "Name": workspace_name }
         }
-    });
-    let cmd_str = serde_json::to_string(&cmd)? + "\n";
-    writer.write_all(cmd_str.as_bytes()).await?;
-    writer.flush().await?;
-    let mut response = String::new();
-    reader.read_line(&mut response).await?;
-    println!("move_to_named_workspace response: {}", response.trim());
-    Ok(())
+    }))
+    .await
+}
+
+/// Float or tile a window
+pub async fn set_floating(win_id: u64, floating: bool) -> Result<()> {
+    let action = if floating {
+        json!({ "MoveWindowToFloating": { "id": win_id } })
+    } else {
+        json!({ "MoveWindowToTiling": { "id": win_id } })
+    };
+    niri_action(action).await
+}
+
+/// Move and resize a floating window
+pub async fn place_floating_window(win_id: u64, geometry: FloatingGeometry) -> Result<()> {
+    niri_action(json!({
+        "MoveFloatingWindow": {
+            "id": win_id,
+            "x": { "SetFixed": geometry.x },
+            "y": { "SetFixed": geometry.y }
+        }
+    }))
+    .await?;
+    niri_action(json!({
+        "SetWindowWidth": { "id": win_id, "change": { "SetFixed": geometry.width } }
+    }))
+    .await?;
+    niri_action(json!({
+        "SetWindowHeight": { "id": win_id, "change": { "SetFixed": geometry.height } }
+    }))
+    .await
+}
+
+/// Focus a window
+pub async fn focus_window(win_id: u64) -> Result<()> {
+    niri_action(json!({ "FocusWindow": { "id": win_id } })).await
+}
+
+/// Move the focused column to a 1-based index
+pub async fn move_column_to_index(index: u64) -> Result<()> {
+    niri_action(json!({ "MoveColumnToIndex": { "index": index } })).await
 }
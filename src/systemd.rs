@@ -0,0 +1,106 @@
+use anyhow::{Context, Result};
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener};
+use std::time::Duration;
+
+/// First file descriptor passed by socket activation (`SD_LISTEN_FDS_START`)
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Control socket passed in by systemd socket activation, if any
+///
+/// Only the first descriptor is used; `LISTEN_PID` must name this process so a
+/// child that inherited the environment does not pick it up.
+pub fn activated_listener() -> Result<Option<UnixListener>> {
+    if !for_this_process("LISTEN_PID") {
+        return Ok(None);
+    }
+    let count: u32 = match std::env::var("LISTEN_FDS") {
+        Ok(count) => count.parse().context("Invalid LISTEN_FDS")?,
+        Err(_) => return Ok(None),
+    };
+    if count == 0 {
+        return Ok(None);
+    }
+    if count > 1 {
+        tracing::warn!("Ignoring {} extra activated sockets", count - 1);
+    }
+
+    // SAFETY: systemd passes ownership of descriptors 3.. to this process, and
+    // nothing else in the daemon uses descriptor 3 without opening it first
+    let listener = unsafe { UnixListener::from_raw_fd(LISTEN_FDS_START) };
+    // SAFETY: plain fcntl on a descriptor we own
+    unsafe { libc::fcntl(LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC) };
+    listener
+        .local_addr()
+        .context("Activated file descriptor is not a Unix socket")?;
+    listener.set_nonblocking(true)?;
+    Ok(Some(listener))
+}
+
+/// Send a state update such as `READY=1` to the service manager
+///
+/// Does nothing unless started by systemd with `NOTIFY_SOCKET` set.
+pub fn notify(state: &str) {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send_notify(&socket.to_string_lossy(), state) {
+        tracing::debug!("Failed to notify service manager: {e:#}");
+    }
+}
+
+fn send_notify(socket: &str, state: &str) -> Result<()> {
+    // A leading '@' names a socket in the abstract namespace
+    let addr = match socket.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(socket)?,
+    };
+    UnixDatagram::unbound()?
+        .send_to_addr(state.as_bytes(), &addr)
+        .with_context(|| format!("Failed to send to {socket}"))?;
+    Ok(())
+}
+
+/// How often to send `WATCHDOG=1`, half the interval systemd expects
+pub fn watchdog_interval() -> Option<Duration> {
+    if std::env::var_os("WATCHDOG_PID").is_some() && !for_this_process("WATCHDOG_PID") {
+        return None;
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Whether a pid variable such as `LISTEN_PID` names this process
+fn for_this_process(var: &str) -> bool {
+    std::env::var(var)
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::process::id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notify_reaches_path_and_abstract_sockets() {
+        let dir = std::env::temp_dir().join(format!("nsticky-notify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        send_notify(path.to_str().unwrap(), "READY=1").unwrap();
+        let mut buf = [0; 64];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let name = format!("nsticky-notify-{}", std::process::id());
+        let addr = SocketAddr::from_abstract_name(&name).unwrap();
+        let receiver = UnixDatagram::bind_addr(&addr).unwrap();
+        send_notify(&format!("@{name}"), "STOPPING=1").unwrap();
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"STOPPING=1");
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::Result;
+
+/// Encode arguments into a single text-protocol line (without the trailing newline)
+///
+/// Plain words are sent as-is. Anything containing whitespace, quotes or
+/// backslashes (or an empty string) is wrapped in double quotes, with `\`, `"`
+/// and control characters escaped so the line never breaks. The CLI speaks the
+/// JSON protocol, so only tests build text lines this way.
+#[cfg(test)]
+pub fn encode_args<S: AsRef<str>>(args: &[S]) -> String {
+    args.iter()
+        .map(|arg| encode_arg(arg.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+fn encode_arg(arg: &str) -> String {
+    let needs_quotes = arg.is_empty()
+        || arg
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '\\' || c.is_control());
+    if !needs_quotes {
+        return arg.to_string();
+    }
+
+    let mut out = String::with_capacity(arg.len() + 2);
+    out.push('"');
+    for c in arg.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Decode a line of the legacy text protocol into its arguments
+///
+/// Inverse of [`encode_args`]. The CLI sends JSON requests; text lines only come
+/// from scripts and older clients. Unquoted words are split on whitespace.
+/// Quoted words keep their whitespace verbatim and may escape `\"`, `\\`, `\n`,
+/// `\r` and `\t`.
+pub fn decode_args(line: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            break;
+        };
+
+        let mut arg = String::new();
+        if first == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => arg.push('\n'),
+                        Some('r') => arg.push('\r'),
+                        Some('t') => arg.push('\t'),
+                        Some(c @ ('"' | '\\')) => arg.push(c),
+                        Some(c) => anyhow::bail!("Invalid escape sequence '\\{c}'"),
+                        None => anyhow::bail!("Unterminated escape sequence"),
+                    },
+                    Some(c) => arg.push(c),
+                    None => anyhow::bail!("Unterminated quoted argument"),
+                }
+            }
+            if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                anyhow::bail!("Unexpected character after closing quote");
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        args.push(arg);
+    }
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(args: &[&str]) {
+        let line = encode_args(args);
+        assert!(!line.contains('\n'), "encoded line must stay on one line");
+        assert_eq!(decode_args(&line).unwrap(), args, "line: {line}");
+    }
+
+    #[test]
+    fn plain_words_are_not_quoted() {
+        assert_eq!(
+            encode_args(&["toggle_appid", "firefox"]),
+            "toggle_appid firefox"
+        );
+        round_trip(&["stage", "--appid", "org.gnome.Nautilus"]);
+    }
+
+    #[test]
+    fn titles_keep_spaces_and_tabs() {
+        round_trip(&["toggle_title", "two  spaces"]);
+        round_trip(&["toggle_title", "  leading and trailing  "]);
+        round_trip(&["toggle_title", "tab\there"]);
+    }
+
+    #[test]
+    fn titles_keep_quotes_and_backslashes() {
+        round_trip(&["toggle_title", "\"quoted\" title"]);
+        round_trip(&["toggle_title", "C:\\path\\file.txt"]);
+        round_trip(&["toggle_title", "ends with backslash \\"]);
+    }
+
+    #[test]
+    fn unicode_and_control_characters() {
+        round_trip(&["toggle_title", "Привет — 日本語 🎉"]);
+        round_trip(&["toggle_title", "line\nbreak\r"]);
+        round_trip(&["toggle_title", ""]);
+    }
+
+    #[test]
+    fn legacy_unquoted_lines_split_on_whitespace() {
+        assert_eq!(decode_args("  add   42 ").unwrap(), ["add", "42"]);
+        assert!(decode_args("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        assert!(decode_args("toggle_title \"unterminated").is_err());
+        assert!(decode_args("toggle_title \"bad \\q escape\"").is_err());
+        assert!(decode_args("toggle_title \"a\"b").is_err());
+    }
+}